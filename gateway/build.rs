@@ -1,17 +1,13 @@
-use std::{
-    env, fs,
-    path::{Path, PathBuf},
-    process::{self},
-};
+use std::{env, path::Path, process};
 
 use static_files::resource_dir;
 
 fn main() -> std::io::Result<()> {
     #[cfg(windows)]
-    pub const YARN: &'static str = "yarn.cmd";
+    pub const YARN: &str = "yarn.cmd";
 
     #[cfg(not(windows))]
-    pub const YARN: &'static str = "yarn";
+    pub const YARN: &str = "yarn";
 
     let cwd = Path::new("./src/front-end/");
     env::set_current_dir(cwd).ok();
@@ -1,40 +1,49 @@
-use std::{collections::HashMap, num::NonZeroUsize, sync::mpsc::Sender};
+use std::num::NonZeroUsize;
 
 use actix::prelude::*;
+use futures::channel::oneshot::Sender;
 use log::warn;
 use lru::LruCache;
-
-use crate::graphql::Person;
+use protocol::messages::ResponseMessageDto;
 
 const LRU_CACHE_SIZE: usize = 500;
 
+/// A response from a backend service, or why the message carrying it could not be read.
+pub type Reply = Result<ResponseMessageDto, String>;
+
+/// Correlates replies from the backend services with the requests waiting for them.
+///
+/// One registry serves every request type; the waiting side picks the reply it expects out of
+/// the response.
 pub struct GlobalActor {
-    persons: LruCache<String, Sender<Vec<Person>>>,
-    person: LruCache<String, Sender<Person>>,
+    pending: LruCache<String, Sender<Reply>>,
 }
 
 impl GlobalActor {
     pub fn new() -> Self {
         Self {
-            persons: LruCache::new(NonZeroUsize::new(LRU_CACHE_SIZE).unwrap()),
-            // person: HashMap::new(),
-            person: LruCache::new(NonZeroUsize::new(LRU_CACHE_SIZE).unwrap()),
+            pending: LruCache::new(NonZeroUsize::new(LRU_CACHE_SIZE).unwrap()),
         }
     }
 }
 
+impl Default for GlobalActor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Actor for GlobalActor {
     type Context = Context<Self>;
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
-
 pub enum GlobalActorMessage {
-    AddPersonsMapping(String, Sender<Vec<Person>>),
-    SendPersonsMessage(String, Vec<Person>),
-    AddPersonMapping(String, Sender<Person>),
-    SendPersonMessage(String, Person),
+    AddMapping(String, Sender<Reply>),
+    SendMessage(String, Reply),
+    /// Forgets a request that will get no reply, or whose reply is no longer awaited.
+    RemoveMapping(String),
 }
 
 impl Handler<GlobalActorMessage> for GlobalActor {
@@ -42,22 +51,20 @@ impl Handler<GlobalActorMessage> for GlobalActor {
 
     fn handle(&mut self, msg: GlobalActorMessage, _ctx: &mut Context<Self>) -> Self::Result {
         match msg {
-            GlobalActorMessage::AddPersonsMapping(request_id, tx) => {
-                self.persons.put(request_id, tx);
-            }
-            GlobalActorMessage::SendPersonsMessage(request_id, persons) => {
-                self.persons.pop(&request_id).map(|tx| tx.send(persons));
+            GlobalActorMessage::AddMapping(request_id, tx) => {
+                self.pending.put(request_id, tx);
             }
-            GlobalActorMessage::AddPersonMapping(request_id, tx) => {
-                self.person.put(request_id, tx);
+            GlobalActorMessage::SendMessage(request_id, reply) => {
+                // The receiver is gone if the resolver already timed out, so a failed send is
+                // only worth a warning.
+                if let Some(tx) = self.pending.pop(&request_id) {
+                    if tx.send(reply).is_err() {
+                        warn!("Reply for request {request_id} arrived after the resolver gave up");
+                    }
+                }
             }
-            GlobalActorMessage::SendPersonMessage(request_id, person) => {
-                self.person
-                    .pop(&request_id)
-                    .map(|tx| match tx.send(person) {
-                        Ok(()) => (),
-                        Err(e) => warn!("Error sending {e:?}"),
-                    });
+            GlobalActorMessage::RemoveMapping(request_id) => {
+                self.pending.pop(&request_id);
             }
         };
     }
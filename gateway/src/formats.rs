@@ -0,0 +1,273 @@
+use std::future::{ready, Ready};
+use std::ops::Deref;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, Accept, Header, HeaderName, Quality};
+use actix_web::http::StatusCode;
+use actix_web::{
+    web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use futures::future::LocalBoxFuture;
+use log::error;
+use mime::Mime;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::problem::Problem;
+
+/// Sent with every response whose format was negotiated, so caches keep one copy per format.
+pub const VARY_ACCEPT: (HeaderName, &str) = (header::VARY, "accept");
+
+/// A format the fruit API reads and writes. JSON is the default; CSV is for spreadsheets, and
+/// MessagePack for clients that want a compact binary form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+    MessagePack,
+    Xml,
+}
+
+impl Format {
+    /// In the order they are picked when `Accept` leaves the choice to the server.
+    pub const ALL: [Format; 4] = [Format::Json, Format::Csv, Format::MessagePack, Format::Xml];
+
+    /// The media type responses in the format have.
+    pub fn media_type(self) -> &'static str {
+        self.media_types()[0]
+    }
+
+    /// A short name for the format, as it appears in `ETag`s.
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::MessagePack => "msgpack",
+            Format::Xml => "xml",
+        }
+    }
+
+    // Every media type the format goes by, the one it is sent as first.
+    fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::Csv => &["text/csv"],
+            Format::MessagePack => &["application/msgpack", "application/x-msgpack"],
+            Format::Xml => &["application/xml", "text/xml"],
+        }
+    }
+
+    // Whether the media type, or range of types such as `text/*`, takes in the format.
+    fn within(self, range: &Mime) -> bool {
+        self.media_types().iter().any(|media_type| {
+            let (type_, subtype) = media_type.split_once('/').expect("a media type has a '/'");
+            range.type_() == mime::STAR
+                || (range.type_() == type_
+                    && (range.subtype() == mime::STAR || range.subtype() == subtype))
+        })
+    }
+
+    /// The format to respond in: the one `Accept` prefers, or JSON if the request has no
+    /// `Accept`. Fails with 406 Not Acceptable if `Accept` takes none of the formats.
+    pub fn negotiate(req: &HttpRequest) -> Result<Format, Problem> {
+        if !req.headers().contains_key(header::ACCEPT) {
+            return Ok(Format::Json);
+        }
+        let items = Accept::parse(req)
+            .map(|accept| accept.0)
+            .unwrap_or_default();
+        let (acceptable, refused): (Vec<_>, Vec<_>) = items
+            .into_iter()
+            .partition(|item| item.quality > Quality::ZERO);
+        Accept(acceptable)
+            .ranked()
+            .iter()
+            .find_map(|range| {
+                Format::ALL.into_iter().find(|format| {
+                    format.within(range) && !refused.iter().any(|item| format.within(&item.item))
+                })
+            })
+            .ok_or_else(|| {
+                Problem::new(
+                    StatusCode::NOT_ACCEPTABLE,
+                    format!(
+                        "Accept takes none of the formats the fruit API responds in: {}",
+                        Format::listed()
+                    ),
+                )
+            })
+    }
+
+    /// The format of the body of the request, by its `Content-Type`. Any `+json` type is JSON.
+    /// Fails with 415 Unsupported Media Type for a body in none of the formats.
+    pub fn of_body(req: &HttpRequest) -> Result<Format, Problem> {
+        let unsupported = |content_type: &str| {
+            Problem::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!(
+                    "The body must be one of {}, not '{content_type}'",
+                    Format::listed()
+                ),
+            )
+        };
+        let content_type = match req.mime_type() {
+            Ok(Some(content_type)) => content_type,
+            Ok(None) => return Err(unsupported("")),
+            Err(_) => return Err(unsupported(req.content_type())),
+        };
+        if content_type.type_() == mime::APPLICATION && content_type.suffix() == Some(mime::JSON) {
+            return Ok(Format::Json);
+        }
+        Format::ALL
+            .into_iter()
+            .find(|format| {
+                format
+                    .media_types()
+                    .iter()
+                    .any(|media_type| content_type.essence_str() == *media_type)
+            })
+            .ok_or_else(|| unsupported(content_type.essence_str()))
+    }
+
+    fn listed() -> String {
+        Format::ALL.map(Format::media_type).join(", ")
+    }
+
+    /// Writes `value` in the format. Fails with a 500 problem, as every body the API sends
+    /// should be writable in every format.
+    pub fn encode<T: Encode>(self, value: &T) -> Result<Vec<u8>, Problem> {
+        let encoded = match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(vec![]);
+                value
+                    .write_csv(&mut writer)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| writer.into_inner().map_err(|e| e.to_string()))
+            }
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Format::Xml => quick_xml::se::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+        };
+        encoded.map_err(|e| {
+            error!("Cannot write a response as {}: {e}", self.media_type());
+            Problem::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("The response cannot be written as {}", self.media_type()),
+            )
+        })
+    }
+
+    /// Reads a `T` in the format from `body`. Fails with a 400 problem naming the `body` field,
+    /// like the other invalid values of a request.
+    pub fn decode<T: Decode>(self, body: &[u8]) -> Result<T, Problem> {
+        let decoded = match self {
+            Format::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+            Format::Csv => T::read_csv(&mut csv::Reader::from_reader(body)),
+            Format::MessagePack => rmp_serde::from_slice(body).map_err(|e| e.to_string()),
+            Format::Xml => std::str::from_utf8(body)
+                .map_err(|e| e.to_string())
+                .and_then(T::read_xml),
+        };
+        decoded.map_err(|message| {
+            Problem::new(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid body: not a valid {}: {message}", self.media_type()),
+            )
+            .with("field", "body")
+        })
+    }
+}
+
+/// A response body the fruit API can write in every format. CSV only has rows of flat records,
+/// so each body says which rows it is.
+pub trait Encode: Serialize {
+    fn write_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> csv::Result<()>;
+}
+
+/// A request body the fruit API can read in every format.
+pub trait Decode: DeserializeOwned {
+    fn read_csv(reader: &mut csv::Reader<&[u8]>) -> Result<Self, String>;
+
+    /// Reads the body as XML; the element names are the field names, as in JSON.
+    fn read_xml(body: &str) -> Result<Self, String> {
+        quick_xml::de::from_str(body).map_err(|e| e.to_string())
+    }
+}
+
+/// Reads a CSV body that holds a single record under its header row.
+pub fn single_record<T: DeserializeOwned>(reader: &mut csv::Reader<&[u8]>) -> Result<T, String> {
+    let mut records = reader.deserialize();
+    match (records.next(), records.next()) {
+        (Some(record), None) => record.map_err(|e| e.to_string()),
+        _ => Err("must hold a header row and exactly one record".to_string()),
+    }
+}
+
+/// Extracts the format to respond in, negotiated from `Accept`. Rejects the request with
+/// 406 Not Acceptable before the handler runs if `Accept` takes none of the formats.
+pub struct Accepted(pub Format);
+
+impl Accepted {
+    /// A response with `value` as its body, in the negotiated format.
+    pub fn respond<T: Encode>(
+        &self,
+        response: HttpResponseBuilder,
+        value: &T,
+    ) -> Result<HttpResponse, Problem> {
+        Ok(self.body(response, self.0.encode(value)?))
+    }
+
+    /// A response with a body already encoded in the negotiated format.
+    pub fn body(&self, mut response: HttpResponseBuilder, body: Vec<u8>) -> HttpResponse {
+        response
+            .content_type(self.0.media_type())
+            .insert_header(VARY_ACCEPT)
+            .body(body)
+    }
+}
+
+impl FromRequest for Accepted {
+    type Error = Problem;
+    type Future = Ready<Result<Self, Problem>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Format::negotiate(req).map(Accepted))
+    }
+}
+
+/// Extracts a request body in any of the formats, like `web::Json` does a JSON one. Responds
+/// with a problem if the body is in none of them, or does not read as a `T`.
+pub struct Decoded<T>(pub T);
+
+impl<T> Decoded<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Decoded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Decode + 'static> FromRequest for Decoded<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let format = Format::of_body(req);
+        let body = web::Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let format = format?;
+            let body = body
+                .await
+                .map_err(|e| Problem::new(e.as_response_error().status_code(), e.to_string()))?;
+            Ok(Decoded(format.decode(&body)?))
+        })
+    }
+}
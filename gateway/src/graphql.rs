@@ -1,57 +1,32 @@
-use crate::actor::GlobalActorMessage;
-use actix::Addr;
-use actix_web::{get, post, web, HttpResponse};
-use async_graphql::{http::GraphiQLSource, Object, SimpleObject};
-use async_graphql::{Context, EmptyMutation, EmptySubscription, MergedObject, Schema};
-use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
-use std::time::{Duration, Instant};
-use uuid::Uuid;
-
-use crate::actor::GlobalActor;
-
-#[derive(Serialize)]
-enum Command {
-    GetPerson,
-    GetPersons,
-}
-
-#[derive(Serialize)]
-struct ServiceRequest {
-    request_id: String,
-    command: Command,
-}
-
-impl ServiceRequest {
-    pub fn get_person(request_id: String) -> Self {
-        Self {
-            request_id,
-            command: Command::GetPerson,
-        }
-    }
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
 
-    pub fn get_persons(request_id: String) -> Self {
-        Self {
-            request_id,
-            command: Command::GetPersons,
-        }
-    }
-}
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use async_graphql::connection::{self, Connection, CursorType, Edge};
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{http::GraphiQLSource, InputObject, Object, SimpleObject, Subscription};
+use async_graphql::{Context, Data, ErrorExtensions, MergedObject, Result, Schema};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
+use futures::future::ready;
+use futures::{stream, Stream, StreamExt};
+use log::warn;
+pub use protocol::events::{PersonChangeKind, PersonChanged};
+use protocol::messages::{
+    CreatePerson, DeletePerson, GetPersons, GetPersonsByIds, PageRequest, UpdatePerson,
+    MAX_PAGE_SIZE,
+};
+pub use protocol::models::{Id, Person};
+use tokio::sync::broadcast::{self, error::RecvError};
 
-#[derive(SimpleObject, Serialize, Deserialize, Debug)]
-pub struct Id {
-    pub number: i32,
-    pub department: String,
-}
+use crate::repository::{self, blocking, FruitRepository, Precondition, RepositoryError};
+use crate::rest::{
+    validate_name, Fruit, FruitError, FruitSort, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT,
+};
+use crate::service::{ServiceClient, ServiceError};
 
-#[derive(SimpleObject, Serialize, Deserialize, Debug)]
-pub struct Person {
-    pub name: String,
-    pub id: Id,
-}
+const DEFAULT_PERSONS_PAGE_SIZE: usize = 20;
 
 #[derive(Default)]
 pub struct EmployeeQuery {
@@ -66,86 +41,446 @@ impl EmployeeQuery {
         self.value
     }
 
-    async fn person<'ctx>(&self, ctx: &Context<'ctx>) -> Option<Person> {
-        let (tx, rx): (Sender<Person>, Receiver<Person>) = mpsc::channel();
-        let addr = ctx.data::<Addr<GlobalActor>>().unwrap();
-        let request_id = Uuid::new_v4();
-        addr.send(GlobalActorMessage::AddPersonMapping(
-            request_id.clone().to_string(),
-            tx,
-        ))
-        .await
-        .unwrap();
-        let producer = (*ctx.data::<FutureProducer>().unwrap()).clone();
-        let payload = json!(ServiceRequest::get_person(request_id.to_string())).to_string();
-        let topic = "from_router";
-        // TODO: There is duplication here with code in the next method. Refactor to reduce duplication.
-        producer
-            .send(
-                FutureRecord::to(topic)
-                    .payload(&payload)
-                    .key(&request_id.to_string()),
-                Duration::from_secs(0),
-            )
+    async fn person<'ctx>(&self, ctx: &Context<'ctx>, number: i32) -> Result<Option<Person>> {
+        let loader = ctx.data::<DataLoader<PersonLoader>>()?;
+        let person = loader.load_one(number).await.map_err(|e| e.extend());
+        Ok(null_on_error(ctx, person).flatten())
+    }
+
+    /// Persons matching every filter that is given, in order of number: the `first` (20 unless
+    /// `last` is given, at most 100) after the `after` cursor, or the `last` before `before`.
+    /// `first` and `last` cannot be given together.
+    /// The user-service reads only the persons of the page.
+    #[allow(clippy::too_many_arguments)]
+    async fn persons<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        department: Option<String>,
+        name_contains: Option<String>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> Result<Option<Connection<Cursor<i32>, Person>>> {
+        let service_client = ctx.data::<ServiceClient>()?;
+        // `connection::query` would refuse the two as well, but without a code.
+        if first.is_some() && last.is_some() {
+            let refused: Result<Connection<Cursor<i32>, Person>> =
+                Err(invalid("last", "cannot be given with first"));
+            return Ok(null_on_error(ctx, refused));
+        }
+        let persons = connection::query(
+            after,
+            before,
+            first,
+            last,
+            |after: Option<Cursor<i32>>, before: Option<Cursor<i32>>, first, last| async move {
+                let (field, limit, from_end) = match (first, last) {
+                    (_, Some(last)) => ("last", last, true),
+                    (first, None) => ("first", first.unwrap_or(DEFAULT_PERSONS_PAGE_SIZE), false),
+                };
+                let limit = u32::try_from(limit)
+                    .ok()
+                    .filter(|limit| *limit <= MAX_PAGE_SIZE)
+                    .ok_or_else(|| {
+                        invalid(field, format_args!("must be at most {MAX_PAGE_SIZE}"))
+                    })?;
+                let query = GetPersons {
+                    department,
+                    name_contains,
+                    page: PageRequest {
+                        after: after.map(|after| after.0),
+                        before: before.map(|before| before.0),
+                        limit,
+                        from_end,
+                    },
+                };
+                let page = service_client
+                    .request(query)
+                    .await
+                    .map_err(|e| e.extend())?;
+                let mut connection = Connection::new(page.has_previous, page.has_next);
+                connection.edges.extend(
+                    page.persons
+                        .into_iter()
+                        .map(|person| Edge::new(Cursor(person.id.number), person)),
+                );
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await;
+        Ok(null_on_error(ctx, persons))
+    }
+}
+
+// An argument the resolver refuses, reported like the validation errors of the user-service.
+fn invalid(field: &'static str, message: impl Display) -> async_graphql::Error {
+    async_graphql::Error::new(format!("Invalid {field}: {message}")).extend_with(|_, e| {
+        e.set("code", "VALIDATION_ERROR");
+        e.set("field", field);
+    })
+}
+
+/// Looks persons up by number for the `person` fields of a query. The fields of a query are
+/// resolved together, so however many of them it has, they cost one `GetPersonsByIds` request.
+pub struct PersonLoader {
+    service_client: ServiceClient,
+}
+
+impl PersonLoader {
+    pub fn new(service_client: ServiceClient) -> Self {
+        Self { service_client }
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Loader<i32> for PersonLoader {
+    type Value = Person;
+    // Every field of a failed request gets its error.
+    type Error = Arc<ServiceError>;
+
+    async fn load(&self, numbers: &[i32]) -> Result<HashMap<i32, Person>, Arc<ServiceError>> {
+        let query = GetPersonsByIds {
+            numbers: numbers.to_vec(),
+        };
+        let persons = self.service_client.request(query).await.map_err(Arc::new)?;
+        Ok(persons
+            .into_iter()
+            .map(|person| (person.id.number, person))
+            .collect())
+    }
+}
+
+/// Changes to persons, applied by the user-service. A change the service refuses is reported as
+/// an error with code `VALIDATION_ERROR` (naming the `field`), `NOT_FOUND` or
+/// `NUMBERS_EXHAUSTED`.
+#[derive(Default)]
+pub struct PersonMutation;
+
+#[Object]
+impl PersonMutation {
+    /// Adds a person under the next free number.
+    async fn create_person<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        department: String,
+    ) -> Result<Option<Person>> {
+        let service_client = ctx.data::<ServiceClient>()?;
+        let mutation = CreatePerson { name, department };
+        Ok(null_on_error(ctx, service_client.request(mutation).await))
+    }
+
+    /// Changes the fields that are given and leaves the others as they are.
+    async fn update_person<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        number: i32,
+        name: Option<String>,
+        department: Option<String>,
+    ) -> Result<Option<Person>> {
+        let service_client = ctx.data::<ServiceClient>()?;
+        let mutation = UpdatePerson {
+            number,
+            name,
+            department,
+        };
+        Ok(null_on_error(ctx, service_client.request(mutation).await))
+    }
+
+    /// Removes the person and returns it as it was.
+    async fn delete_person<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        number: i32,
+    ) -> Result<Option<Person>> {
+        let service_client = ctx.data::<ServiceClient>()?;
+        let person = service_client.request(DeletePerson { number }).await;
+        Ok(null_on_error(ctx, person))
+    }
+}
+
+/// A fruit as the REST API serves it.
+pub struct FruitObject(pub Fruit);
+
+#[Object(name = "Fruit")]
+impl FruitObject {
+    async fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Bumped on every change; `ifVersion` takes it.
+    async fn version(&self) -> u64 {
+        self.0.version
+    }
+}
+
+// The resolvers report the errors with a `code`, as they do those of the backend services.
+impl ErrorExtensions for FruitError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string()).extend_with(|_, e| {
+            e.set("code", self.code());
+            if let FruitError::Invalid { field, .. } = self {
+                e.set("field", *field);
+            }
+        })
+    }
+}
+
+/// Fruits, read from the same store as the REST API under `/api/v2/fruits`.
+#[derive(Default)]
+pub struct FruitQuery;
+
+#[Object]
+impl FruitQuery {
+    async fn fruit<'ctx>(&self, ctx: &Context<'ctx>, id: u32) -> Result<Option<FruitObject>> {
+        let repository = ctx.data::<Arc<dyn FruitRepository>>()?;
+        let fruit = blocking(repository, move |repository| repository.get(id))
             .await
-            .unwrap();
-        let duration = Duration::new(2, 0);
-        let start_time = Instant::now();
-        loop {
-            let now = Instant::now();
-            if start_time + duration > now {
-                let duration = start_time + duration - now;
-                match rx.recv_timeout(duration) {
-                    Ok(p) => return Some(p),
-                    Err(RecvTimeoutError::Timeout) => break,
-                    Err(RecvTimeoutError::Disconnected) => break,
+            .map_err(FruitError::from);
+        Ok(null_on_error(ctx, fruit).flatten().map(FruitObject))
+    }
+
+    /// Fruits in the order of their ids, `first` (20 unless given, at most 100) at a time.
+    async fn fruits<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        filter: Option<FruitFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Option<Connection<Cursor<u32>, FruitObject, FruitConnectionFields>>> {
+        let repository = ctx.data::<Arc<dyn FruitRepository>>()?;
+        let fruits = connection::query(
+            after,
+            None,
+            first,
+            None,
+            |after: Option<Cursor<u32>>, _, first, _| async move {
+                let limit = first.unwrap_or(DEFAULT_PAGE_LIMIT);
+                if limit > MAX_PAGE_LIMIT {
+                    return Err(FruitError::Invalid {
+                        field: "first",
+                        message: format!("must be at most {MAX_PAGE_LIMIT}"),
+                    }
+                    .extend());
                 }
-            } else {
-                break;
+                // A cursor is the id of its fruit, so a page starts after the last fruit seen
+                // even if fruits before it were created or deleted since. One fruit more than
+                // the page holds tells whether there is a next page.
+                let after_id = after.map(|after| after.0);
+                let query = repository::FruitQuery {
+                    name_prefix: filter.and_then(|filter| filter.name_prefix),
+                    after_id,
+                    sort: FruitSort::IdAscending,
+                    offset: 0,
+                    limit: limit + 1,
+                };
+                let (mut fruits, total) =
+                    blocking(repository, move |repository| repository.list(&query))
+                        .await
+                        .map_err(|e| FruitError::from(e).extend())?;
+                let has_next = fruits.len() > limit;
+                fruits.truncate(limit);
+                let mut connection = Connection::with_additional_fields(
+                    after_id.is_some(),
+                    has_next,
+                    FruitConnectionFields { total_count: total },
+                );
+                connection.edges.extend(
+                    fruits
+                        .into_iter()
+                        .map(|fruit| Edge::new(Cursor(fruit.id), FruitObject(fruit))),
+                );
+                Ok(connection)
+            },
+        )
+        .await;
+        Ok(null_on_error(ctx, fruits))
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct FruitFilter {
+    /// Only fruits whose name starts with this, ignoring case.
+    pub name_prefix: Option<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct FruitConnectionFields {
+    /// How many fruits match the filter, on every page.
+    pub total_count: usize,
+}
+
+/// Changes to fruits, made in the same store as the REST API. A change the store refuses is
+/// reported as an error with code `VALIDATION_ERROR` (naming the `field`), `NOT_FOUND` or
+/// `PRECONDITION_FAILED`.
+#[derive(Default)]
+pub struct FruitMutation;
+
+#[Object]
+impl FruitMutation {
+    /// Adds a fruit under a new id.
+    async fn create_fruit<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+    ) -> Result<Option<FruitObject>> {
+        let repository = ctx.data::<Arc<dyn FruitRepository>>()?;
+        let fruit = match validate_name(&name) {
+            Ok(name) => blocking(repository, move |repository| repository.create(name))
+                .await
+                .map_err(FruitError::from),
+            Err(e) => Err(e),
+        };
+        Ok(null_on_error(ctx, fruit).map(FruitObject))
+    }
+
+    /// Renames the fruit. Given `ifVersion`, the fruit is only renamed at that version.
+    async fn update_fruit<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        id: u32,
+        name: String,
+        if_version: Option<u64>,
+    ) -> Result<Option<FruitObject>> {
+        let repository = ctx.data::<Arc<dyn FruitRepository>>()?;
+        let fruit = match validate_name(&name) {
+            Ok(name) => {
+                let fruit = Fruit {
+                    id,
+                    name,
+                    version: 0,
+                };
+                blocking(repository, move |repository| {
+                    repository.put(fruit, &only_at(if_version))
+                })
+                .await
+                .map(|(_, fruit)| fruit)
+                .map_err(|e| refused(id, if_version, e))
             }
-        }
-        None
+            Err(e) => Err(e),
+        };
+        Ok(null_on_error(ctx, fruit).map(FruitObject))
     }
 
-    async fn persons<'ctx>(&self, ctx: &Context<'ctx>) -> Option<Vec<Person>> {
-        let (tx, rx): (Sender<Vec<Person>>, Receiver<Vec<Person>>) = mpsc::channel();
-        let addr = ctx.data::<Addr<GlobalActor>>().unwrap();
-        let request_id = Uuid::new_v4();
-        addr.send(GlobalActorMessage::AddPersonsMapping(
-            request_id.to_string(),
-            tx,
-        ))
+    /// Removes the fruit and returns it as it was. Given `ifVersion`, the fruit is only removed
+    /// at that version.
+    async fn delete_fruit<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        id: u32,
+        if_version: Option<u64>,
+    ) -> Result<Option<FruitObject>> {
+        let repository = ctx.data::<Arc<dyn FruitRepository>>()?;
+        let fruit = blocking(repository, move |repository| {
+            repository.delete(id, &only_at(if_version))
+        })
         .await
-        .unwrap();
-        let producer = (*ctx.data::<FutureProducer>().unwrap()).clone();
-        let payload = json!(ServiceRequest::get_persons(request_id.to_string())).to_string();
-        let topic = "from_router";
-        producer
-            .send(
-                FutureRecord::to(topic)
-                    .payload(&payload)
-                    .key(&request_id.to_string()),
-                Duration::from_secs(0),
-            )
-            .await
-            .unwrap();
+        .map_err(|e| refused(id, if_version, e))
+        .and_then(|fruit| fruit.ok_or(FruitError::NotFound { id }));
+        Ok(null_on_error(ctx, fruit).map(FruitObject))
+    }
+}
+
+// The mutations change only a fruit that exists, at `if_version` if it is given.
+fn only_at(if_version: Option<u64>) -> Precondition {
+    match if_version {
+        Some(version) => Precondition::Versions(vec![version]),
+        None => Precondition::Exists,
+    }
+}
+
+// Without `ifVersion` the only precondition is that the fruit exists.
+fn refused(id: u32, if_version: Option<u64>, e: RepositoryError) -> FruitError {
+    match e {
+        RepositoryError::PreconditionFailed { .. } if if_version.is_none() => {
+            FruitError::NotFound { id }
+        }
+        e => e.into(),
+    }
+}
 
-        let duration = Duration::new(2, 0);
-        let start_time = Instant::now();
+#[derive(MergedObject, Default)]
+pub struct MutationRoot(PersonMutation, FruitMutation);
+
+/// An opaque connection cursor: the position, or key, of an edge, base64 encoded so clients
+/// pass it back as it is rather than make their own.
+pub struct Cursor<T>(pub T);
+
+const CURSOR_PREFIX: &str = "cursor:";
+
+impl<T: Display + FromStr> CursorType for Cursor<T> {
+    type Error = String;
+
+    fn decode_cursor(s: &str) -> Result<Self, String> {
+        base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| decoded.strip_prefix(CURSOR_PREFIX)?.parse().ok())
+            .map(Cursor)
+            .ok_or_else(|| format!("'{s}' is not a cursor of this connection"))
+    }
+
+    fn encode_cursor(&self) -> String {
+        base64::encode_config(
+            format!("{CURSOR_PREFIX}{}", self.0),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Changes to persons as the user-service applies them. Given a department, only changes to
+    /// persons in it, or moving into or out of it, are sent.
+    async fn person_changed<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        department: Option<String>,
+    ) -> Result<impl Stream<Item = PersonChanged>> {
+        let receiver = ctx.data::<broadcast::Sender<PersonChanged>>()?.subscribe();
+        Ok(changes(receiver).filter(move |change| {
+            ready(match &department {
+                Some(department) => change.touches_department(department),
+                None => true,
+            })
+        }))
+    }
+}
+
+fn changes(receiver: broadcast::Receiver<PersonChanged>) -> impl Stream<Item = PersonChanged> {
+    stream::unfold(receiver, |mut receiver| async move {
         loop {
-            let now = Instant::now();
-            if start_time + duration > now {
-                let duration = start_time + duration - now;
-                match rx.recv_timeout(duration) {
-                    Ok(ps) => return Some(ps),
-                    Err(RecvTimeoutError::Timeout) => break,
-                    Err(RecvTimeoutError::Disconnected) => break,
+            match receiver.recv().await {
+                Ok(change) => return Some((change, receiver)),
+                // A subscriber that falls behind misses the oldest changes rather than
+                // holding up the others.
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("A subscription fell behind and missed {skipped} person changes")
                 }
-            } else {
-                break;
+                Err(RecvError::Closed) => return None,
             }
         }
-        None
+    })
+}
+
+// Reports a failed backend request or store access as an error on this field only. Returning the
+// error from the resolver would null out the whole `data` object, hiding the fields that did
+// resolve.
+fn null_on_error<T, E: ErrorExtensions>(ctx: &Context<'_>, result: Result<T, E>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            ctx.add_error(ctx.set_error_path(e.extend().into_server_error(ctx.item.pos)));
+            None
+        }
     }
 }
 
@@ -162,7 +497,7 @@ impl PetQuery {
 }
 
 #[derive(MergedObject, Default)]
-pub struct MergedQuery(EmployeeQuery, PetQuery);
+pub struct MergedQuery(EmployeeQuery, PetQuery, FruitQuery);
 
 // This is route to the IDE - note the 'i'
 #[get("/graphiql")]
@@ -172,13 +507,62 @@ pub async fn index_graphiql() -> HttpResponse {
         .body(
             GraphiQLSource::build()
                 .endpoint("http://localhost:8080/graphql")
+                .subscription_endpoint("ws://localhost:8080/graphql")
                 .finish(),
         )
 }
 
-type MySchema = Schema<MergedQuery, EmptyMutation, EmptySubscription>;
+pub type MySchema = Schema<MergedQuery, MutationRoot, SubscriptionRoot>;
 
+/// `person_changes` is the channel the `PersonChangeConsumer` broadcasts on, and
+/// `fruit_repository` the store the REST fruit handlers use.
+pub fn build_schema(
+    service_client: ServiceClient,
+    person_changes: broadcast::Sender<PersonChanged>,
+    fruit_repository: Arc<dyn FruitRepository>,
+) -> MySchema {
+    Schema::build(
+        MergedQuery::default(),
+        MutationRoot::default(),
+        SubscriptionRoot,
+    )
+    .data(service_client)
+    .data(person_changes)
+    .data(fruit_repository)
+    .finish()
+}
+
+/// Executes a query or mutation. `service_client` is the one the schema was built with.
 #[post("/graphql")]
-pub async fn graphql_post(schema: web::Data<MySchema>, req: GraphQLRequest) -> GraphQLResponse {
-    schema.execute(req.into_inner()).await.into()
+pub async fn graphql_post(
+    schema: web::Data<MySchema>,
+    service_client: web::Data<ServiceClient>,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    // A loader of its own, so the person lookups of one request are never batched with another's,
+    // nor wait on them.
+    schema
+        .execute(req.into_inner().data(person_loader(&service_client)))
+        .await
+        .into()
+}
+
+/// Serves subscriptions over WebSocket, speaking graphql-ws or graphql-transport-ws as the
+/// client asks. Queries sent over the socket share one person loader per connection.
+#[get("/graphql")]
+pub async fn graphql_ws(
+    schema: web::Data<MySchema>,
+    service_client: web::Data<ServiceClient>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> actix_web::Result<HttpResponse> {
+    let mut data = Data::default();
+    data.insert(person_loader(&service_client));
+    GraphQLSubscription::new(MySchema::clone(&schema))
+        .with_data(data)
+        .start(&req, payload)
+}
+
+fn person_loader(service_client: &ServiceClient) -> DataLoader<PersonLoader> {
+    DataLoader::new(PersonLoader::new(service_client.clone()), actix_rt::spawn)
 }
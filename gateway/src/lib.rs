@@ -1,9 +1,15 @@
 pub mod actor;
+pub mod formats;
 pub mod graphql;
 pub mod kafka_consumer;
-pub mod kafka_producer;
 pub mod models;
+pub mod openapi;
+pub mod outbox;
+pub mod problem;
+pub mod repository;
 pub mod rest;
+pub mod routes;
+pub mod service;
 pub mod simple;
 pub mod v1;
 pub mod v2;
@@ -1,33 +1,32 @@
-use std::sync::Mutex;
-
 use actix::prelude::*;
 use actix_web::{web, App, HttpServer};
 use actix_web_static_files::ResourceFiles;
-use async_graphql::{EmptyMutation, EmptySubscription, Schema};
 use gateway::{
     actor::GlobalActor,
-    graphql::{graphql_post, index_graphiql, MergedQuery},
-    kafka_consumer::IngestConsumer,
-    kafka_producer::create_kafka_producer,
-    rest::{delete_fruit, get_fruit, get_fruits, update_fruit, Fruit, FruitList},
-    simple::{
-        api_get_hello, api_get_hello_b, api_get_my_animal_result_responder, echo, hello,
-        post_with_body_deserialized,
-    },
-    v1::{api_v1_get_hello, api_v1_get_hello_b},
-    v2::{
-        api_v2_get_hello, api_v2_get_hello_b, api_v2_get_hello_b_query_params,
-        path_dynamic_segments, path_struct, path_struct_path_query,
+    graphql::build_schema,
+    kafka_consumer::{IngestConsumer, PersonChangeConsumer},
+    outbox::OutboxRelay,
+    problem::RequestIds,
+    repository::{
+        FruitRepository, InMemoryFruitRepository, JsonFileFruitRepository, SqliteFruitRepository,
     },
-    web_socket::web_socket::index,
+    rest::Fruit,
+    routes::configure,
+    service::ServiceClient,
 };
+use protocol::events::{DEFAULT_FRUIT_EVENTS_TOPIC, PERSON_CHANGES_TOPIC};
 use std::env;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use transport::{kafka::KafkaTransport, StartFrom, Transport};
 
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
 const DEFAULT_BROKERS: &str = "localhost:29092";
-const DEFAULT_CONSUMER_GROUP_ID: &str = "1";
-const DEFAULT_LISTEN_TOPIC: &str = "from_service";
+const DEFAULT_FRUIT_STORE: &str = "memory";
+const REPLY_TOPIC_PREFIX: &str = "from_service";
+// How many person changes a slow subscription may fall behind before it starts missing some.
+const PERSON_CHANGES_CAPACITY: usize = 256;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -37,14 +36,13 @@ async fn main() -> std::io::Result<()> {
 
     let global_actor_address = GlobalActor::new().start();
 
-    let fruit_list = web::Data::new(FruitList {
-        fruits: Mutex::new(vec![Fruit {
-            id: 5,
-            name: "pear".to_string(),
-        }]),
-    });
+    let fruit_repository = open_fruit_repository();
 
-    // take brokers and topics to listen to, from arg, or environment, or default
+    // Each gateway instance owns a reply topic and a consumer group, so replies to its requests
+    // never land on another replica.
+    let instance_id = instance_id();
+
+    // take brokers, consumer group and reply topic, from arg, or environment, or default
     let mut args: Vec<String> = std::env::args().collect();
     args.remove(0);
     let brokers = args
@@ -53,70 +51,134 @@ async fn main() -> std::io::Result<()> {
         .unwrap_or(DEFAULT_BROKERS.to_string());
     let group_id = args
         .pop()
-        .or(env::var("USER_SERVICE_CONSUMER_GROUP_ID").ok())
-        .unwrap_or(DEFAULT_CONSUMER_GROUP_ID.to_string());
-    let listen_topics = args
+        .or(env::var("GATEWAY_CONSUMER_GROUP_ID").ok())
+        .unwrap_or(format!("gateway-{instance_id}"));
+    let reply_topic = args
         .pop()
-        .or(env::var("USER_SERVICE_LISTEN_TOPICS").ok())
-        .map(|l_ts| {
-            l_ts.split(",")
-                .map(|x| x.to_owned())
-                .collect::<Vec<String>>()
-        })
-        .unwrap_or(vec![DEFAULT_LISTEN_TOPIC.to_string()]);
+        .or(env::var("GATEWAY_REPLY_TOPIC").ok())
+        .unwrap_or(format!("{REPLY_TOPIC_PREFIX}.{instance_id}"));
 
-    let producer = create_kafka_producer(&brokers).expect("Could not create Kafka producer");
-    let schema = Schema::build(MergedQuery::default(), EmptyMutation, EmptySubscription)
-        .data(global_actor_address.clone())
-        .data(producer)
-        .finish();
+    let transport: Arc<dyn Transport> =
+        Arc::new(KafkaTransport::new(&brokers).expect("Could not create Kafka transport"));
+    transport
+        .create_topic(&reply_topic)
+        .await
+        .expect("Could not create reply topic");
+    transport
+        .create_topic(PERSON_CHANGES_TOPIC)
+        .await
+        .expect("Could not create person changes topic");
+
+    // Fruit changes are published from the outbox of the fruit store, so one made while Kafka is
+    // down is published once it is back.
+    let fruit_events_topic =
+        env::var("GATEWAY_FRUIT_EVENTS_TOPIC").unwrap_or(DEFAULT_FRUIT_EVENTS_TOPIC.to_string());
+    transport
+        .create_topic(&fruit_events_topic)
+        .await
+        .expect("Could not create fruit events topic");
+    let outbox_relay = OutboxRelay::new(
+        fruit_repository.clone(),
+        transport.clone(),
+        fruit_events_topic,
+    );
+    actix_rt::spawn(async move { outbox_relay.run().await });
+
+    let (person_changes, _) = broadcast::channel(PERSON_CHANGES_CAPACITY);
+    let service_client = ServiceClient::new(
+        global_actor_address.clone(),
+        transport.clone(),
+        reply_topic.clone(),
+    );
+    let schema = build_schema(
+        service_client.clone(),
+        person_changes.clone(),
+        fruit_repository.clone(),
+    );
+    // The REST handlers and the GraphQL resolvers share the one fruit store.
+    let fruit_repository = web::Data::from(fruit_repository);
 
     println!("GraphiQL IDE: http://localhost:8080/graphql");
 
-    let ingest_consumer =
-        IngestConsumer::new(&brokers, &group_id, listen_topics, global_actor_address)
-            .expect("failed to make ingest consumer");
+    // The reply topic belongs to this instance alone, so replies published before the first
+    // assignment are still meant for it.
+    let subscription = transport
+        .subscribe(&group_id, &[&reply_topic], StartFrom::Earliest)
+        .await
+        .expect("failed to subscribe to reply topic");
+    let mut ingest_consumer = IngestConsumer::new(subscription, global_actor_address);
 
     actix_rt::spawn(async move { ingest_consumer.run().await });
 
+    // Every instance needs every change for its own subscribers, hence a group per instance.
+    // Subscriptions only see changes made after they start, so older ones are skipped.
+    let subscription = transport
+        .subscribe(
+            &format!("{group_id}.{PERSON_CHANGES_TOPIC}"),
+            &[PERSON_CHANGES_TOPIC],
+            StartFrom::Latest,
+        )
+        .await
+        .expect("failed to subscribe to person changes topic");
+    let mut person_change_consumer = PersonChangeConsumer::new(subscription, person_changes);
+    actix_rt::spawn(async move { person_change_consumer.run().await });
+
     HttpServer::new(move || {
         let generated = generate(); // For serving the React App
         App::new()
-            .app_data(fruit_list.clone())
+            .wrap(RequestIds)
+            .app_data(fruit_repository.clone())
             .app_data(web::Data::new(schema.clone()))
-            .route("/ws/", web::get().to(index))
-            .service(hello)
-            .service(echo)
-            .service(api_get_my_animal_result_responder)
-            .service(post_with_body_deserialized)
-            .service(index_graphiql)
-            .service(graphql_post)
-            .service(
-                web::scope("/api")
-                    .service(api_get_hello)
-                    .service(api_get_hello_b)
-                    .service(
-                        web::scope("/v1")
-                            .service(api_v1_get_hello)
-                            .service(api_v1_get_hello_b),
-                    )
-                    .service(
-                        web::scope("/v2")
-                            .service(api_v2_get_hello)
-                            .service(api_v2_get_hello_b)
-                            .service(api_v2_get_hello_b_query_params)
-                            .service(path_dynamic_segments)
-                            .service(path_struct)
-                            .service(path_struct_path_query)
-                            .service(get_fruit)
-                            .service(update_fruit)
-                            .service(delete_fruit)
-                            .service(get_fruits),
-                    ),
-            )
+            .app_data(web::Data::new(service_client.clone()))
+            .configure(configure)
             .service(ResourceFiles::new("/", generated)) // Serves the React App
     })
     .bind(("0.0.0.0", 8080))?
     .run()
     .await
 }
+
+// GATEWAY_INSTANCE_ID names the instance, e.g. after its pod; without it the host name is used.
+// Either is stable across restarts, so a restarted gateway reuses its reply topic and consumer
+// group rather than leaving them behind in Kafka and creating new ones.
+fn instance_id() -> String {
+    env::var("GATEWAY_INSTANCE_ID")
+        .or_else(|_| env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| {
+            panic!("Set GATEWAY_INSTANCE_ID: the host name, its fallback, could not be read")
+        })
+}
+
+// GATEWAY_FRUIT_STORE picks where fruits are kept: "memory" (the default), "json" or "sqlite".
+// The file-based stores use GATEWAY_FRUIT_STORE_PATH, and are seeded when the file is new.
+fn open_fruit_repository() -> Arc<dyn FruitRepository> {
+    let seed = vec![Fruit {
+        id: 5,
+        name: "pear".to_string(),
+        version: 1,
+    }];
+    let store = env::var("GATEWAY_FRUIT_STORE").unwrap_or(DEFAULT_FRUIT_STORE.to_string());
+    let path = env::var("GATEWAY_FRUIT_STORE_PATH");
+    match store.as_str() {
+        "memory" => Arc::new(InMemoryFruitRepository::new(seed)),
+        "json" => {
+            let path = path.unwrap_or("fruits.json".to_string());
+            Arc::new(
+                JsonFileFruitRepository::open(&path, seed)
+                    .unwrap_or_else(|e| panic!("Could not open fruit store {path}: {e}")),
+            )
+        }
+        "sqlite" => {
+            let path = path.unwrap_or("fruits.db".to_string());
+            Arc::new(
+                SqliteFruitRepository::open(&path, seed)
+                    .unwrap_or_else(|e| panic!("Could not open fruit store {path}: {e}")),
+            )
+        }
+        other => panic!("Unknown GATEWAY_FRUIT_STORE {other:?}; use memory, json or sqlite"),
+    }
+}
@@ -1,13 +1,14 @@
 
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema, IntoParams)]
 pub struct Animal {
     pub age: u32,
     pub animal: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema, IntoParams)]
 pub struct Input {
     pub required_input: String,
     pub maybe_other_input: Option<String>,
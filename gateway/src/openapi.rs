@@ -0,0 +1,161 @@
+use actix_web::http::header::ContentType;
+use actix_web::{get, HttpResponse};
+use utoipa::openapi::path::PathItemType;
+use utoipa::openapi::{self, Content, ObjectBuilder, Ref, ResponseBuilder, SchemaType};
+use utoipa::{Modify, OpenApi};
+
+use crate::formats::Format;
+use crate::models::{Animal, Input};
+use crate::problem::{Problem, PROBLEM_JSON};
+use crate::rest::{
+    Fruit, FruitBatch, FruitBatchResults, FruitOperation, FruitOperationResult, FruitPage,
+    FruitSort, NewFruit, JSON_PATCH,
+};
+
+/// The OpenAPI 3 description of the `/api` scopes, generated from the annotations on their
+/// handlers and the models they take and return.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        description = "The REST API of the gateway, under `/api`",
+        license(name = "Apache-2.0")
+    ),
+    paths(
+        crate::simple::api_get_hello,
+        crate::simple::api_get_hello_b,
+        crate::v1::api_v1_get_hello,
+        crate::v1::api_v1_get_hello_b,
+        crate::v2::api_v2_get_hello,
+        crate::v2::api_v2_get_hello_b,
+        crate::v2::api_v2_get_hello_b_query_params,
+        crate::v2::path_dynamic_segments,
+        crate::v2::path_struct,
+        crate::v2::path_struct_path_query,
+        crate::rest::get_fruits,
+        crate::rest::create_fruit,
+        crate::rest::batch_fruits,
+        crate::rest::get_fruit,
+        crate::rest::update_fruit,
+        crate::rest::patch_fruit,
+        crate::rest::delete_fruit,
+    ),
+    components(schemas(
+        Fruit,
+        NewFruit,
+        FruitSort,
+        FruitPage,
+        FruitBatch,
+        FruitOperation,
+        FruitOperationResult,
+        FruitBatchResults,
+        Animal,
+        Input,
+        Problem,
+    )),
+    modifiers(&JsonPatchBody, &OtherFormats),
+    tags(
+        (name = "fruits", description = "Fruits, kept in the store `GATEWAY_FRUIT_STORE` picks"),
+        (name = "animals", description = "Path and query parameter examples"),
+        (name = "hello", description = "Plain-text greetings from each scope"),
+    )
+)]
+pub struct ApiDoc;
+
+// `#[utoipa::path]` takes one content type per request body, so the JSON patch form of the
+// PATCH body is added here.
+struct JsonPatchBody;
+
+impl Modify for JsonPatchBody {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        let body = openapi
+            .paths
+            .paths
+            .get_mut("/api/v2/fruits/{id}")
+            .and_then(|path| path.operations.get_mut(&PathItemType::Patch))
+            .and_then(|operation| operation.request_body.as_mut());
+        if let Some(body) = body {
+            let operations = ObjectBuilder::new().schema_type(SchemaType::Object);
+            let patch = openapi::ArrayBuilder::new().items(operations);
+            body.content
+                .insert(JSON_PATCH.to_string(), Content::new(patch.build()));
+        }
+    }
+}
+
+// The fruit handlers read and write every format of `Format`, but are annotated with JSON bodies
+// only, so the other formats are added here, with the 406 of a request for none of them.
+struct OtherFormats;
+
+impl Modify for OtherFormats {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        let json = Format::Json.media_type();
+        let others = || Format::ALL.into_iter().skip(1).map(Format::media_type);
+        let fruit_paths = openapi
+            .paths
+            .paths
+            .iter_mut()
+            .filter(|(path, _)| path.starts_with("/api/v2/fruits"));
+        for (_, path) in fruit_paths {
+            for operation in path.operations.values_mut() {
+                if let Some(body) = operation.request_body.as_mut() {
+                    if let Some(content) = body.content.get(json).cloned() {
+                        body.content
+                            .extend(others().map(|other| (other.to_string(), content.clone())));
+                    }
+                }
+                let mut negotiated = false;
+                for response in operation.responses.responses.values_mut() {
+                    if let openapi::RefOr::T(response) = response {
+                        if let Some(content) = response.content.get(json).cloned() {
+                            negotiated = true;
+                            for other in others() {
+                                response.content.insert(other.to_string(), content.clone());
+                            }
+                        }
+                    }
+                }
+                if negotiated {
+                    let not_acceptable = ResponseBuilder::new()
+                        .description("`Accept` takes none of the formats")
+                        .content(PROBLEM_JSON, Content::new(Ref::from_schema_name("Problem")));
+                    operation
+                        .responses
+                        .responses
+                        .insert("406".to_string(), not_acceptable.build().into());
+                }
+            }
+        }
+    }
+}
+
+// Swagger UI, loaded from a CDN so the gateway does not have to bundle it.
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8" />
+    <title>Gateway API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@4.15.5/swagger-ui.css" />
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@4.15.5/swagger-ui-bundle.js" crossorigin></script>
+    <script>
+        window.onload = () => {
+            window.ui = SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+        };
+    </script>
+</body>
+</html>
+"##;
+
+#[get("/openapi.json")]
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[get("/docs")]
+async fn api_docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(SWAGGER_UI)
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_rt::time::sleep;
+use log::warn;
+use transport::Transport;
+
+use crate::repository::{blocking, FruitRepository};
+
+// How long the relay waits before looking at an empty outbox again.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// How many events the relay publishes before it removes them from the outbox.
+const RELAY_BATCH_SIZE: usize = 100;
+
+/// Publishes the events a fruit repository keeps in its outbox, oldest first, and removes them
+/// from the outbox once the transport has taken them.
+///
+/// The repository records an event in the same write as the change, so a change that was made
+/// is published even if the transport is down at the time, or the gateway stops before it is.
+pub struct OutboxRelay {
+    repository: Arc<dyn FruitRepository>,
+    transport: Arc<dyn Transport>,
+    topic: String,
+}
+
+impl OutboxRelay {
+    pub fn new(
+        repository: Arc<dyn FruitRepository>,
+        transport: Arc<dyn Transport>,
+        topic: String,
+    ) -> Self {
+        Self {
+            repository,
+            transport,
+            topic,
+        }
+    }
+
+    /// Publishes the pending events until the outbox is empty or publishing fails, and returns
+    /// how many were published.
+    pub async fn publish_pending(&self) -> usize {
+        let mut published = 0;
+        loop {
+            let pending = blocking(&self.repository, |repository| {
+                repository.pending_events(RELAY_BATCH_SIZE)
+            });
+            let events = match pending.await {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Could not read the fruit event outbox: {e}");
+                    return published;
+                }
+            };
+            // A full batch may have more events behind it.
+            let mut more = events.len() == RELAY_BATCH_SIZE;
+            let mut last_published = None;
+            for event in events {
+                let payload = serde_json::to_string(&event).expect("an event is always valid JSON");
+                let key = event.fruit_id().to_string();
+                if let Err(e) = self.transport.publish(&self.topic, &key, &payload).await {
+                    warn!("Could not publish fruit event {}: {e}", event.sequence);
+                    more = false;
+                    break;
+                }
+                last_published = Some(event.sequence);
+                published += 1;
+            }
+            // Events whose removal fails stay in the outbox, and are published again.
+            if let Some(sequence) = last_published {
+                let removed = blocking(&self.repository, move |repository| {
+                    repository.remove_events(sequence)
+                });
+                if let Err(e) = removed.await {
+                    warn!("Could not remove published fruit events from the outbox: {e}");
+                    return published;
+                }
+            }
+            if !more {
+                return published;
+            }
+        }
+    }
+
+    /// Publishes pending events for as long as the gateway runs.
+    pub async fn run(&self) {
+        loop {
+            self.publish_pending().await;
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
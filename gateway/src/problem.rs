@@ -0,0 +1,217 @@
+use std::fmt;
+use std::future::{ready, Ready};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpResponse, ResponseError};
+use futures::future::LocalBoxFuture;
+use serde_json::{json, Map, Value};
+use utoipa::openapi::{KnownFormat, ObjectBuilder, RefOr, Schema, SchemaFormat, SchemaType};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+tokio::task_local! {
+    // The id of the request being handled, set by `RequestIds`.
+    static REQUEST_ID: String;
+}
+
+/// Why a request to the HTTP API failed, rendered as an RFC 7807 `application/problem+json`
+/// response. Every error of the REST handlers and their extractors ends up as one of these,
+/// directly or through an error type of its own, such as `FruitError`.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    status: StatusCode,
+    detail: String,
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            detail: detail.into(),
+            extensions: Map::new(),
+        }
+    }
+
+    /// Adds a member of its own to the problem, next to the standard ones.
+    pub fn with(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.extensions.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// The problem as JSON, without the request id, as embedded in other responses.
+    pub fn body(&self) -> Value {
+        let mut body = json!({
+            "type": "about:blank",
+            "title": self.status.canonical_reason(),
+            "status": self.status.as_u16(),
+            "detail": self.detail,
+        });
+        for (name, value) in &self.extensions {
+            body[name] = value.clone();
+        }
+        body
+    }
+}
+
+// Written out rather than derived, as the members are built in `body` and `error_response`.
+impl<'s> ToSchema<'s> for Problem {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let string = |description: &str| {
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .description(Some(description))
+        };
+        let schema = ObjectBuilder::new()
+            .description(Some(
+                "An RFC 7807 problem. Some problems have members of their own, such as `field`.",
+            ))
+            .property("type", string("Always `about:blank`"))
+            .required("type")
+            .property("title", string("The reason phrase of the status"))
+            .required("title")
+            .property(
+                "status",
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::Integer)
+                    .format(Some(SchemaFormat::KnownFormat(KnownFormat::Int32))),
+            )
+            .required("status")
+            .property("detail", string("What went wrong with this request"))
+            .required("detail")
+            .property(
+                "request_id",
+                string("The id of the request, as in its `X-Request-Id` response header"),
+            )
+            .property("field", string("The member of the request that is invalid"))
+            .build();
+        ("Problem", schema.into())
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+impl std::error::Error for Problem {}
+
+impl ResponseError for Problem {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = self.body();
+        if let Ok(request_id) = REQUEST_ID.try_with(String::clone) {
+            body["request_id"] = json!(request_id);
+        }
+        HttpResponse::build(self.status)
+            .content_type(PROBLEM_JSON)
+            .json(body)
+    }
+}
+
+/// Renders the requests `web::Json` rejects as problems: 415 for the wrong content type, 413
+/// for a body over the limit and 400 for one that does not deserialize.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e, _| {
+        let status = match &e {
+            JsonPayloadError::ContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Problem::new(status, e.to_string()).into()
+    })
+}
+
+/// Renders the requests `web::Query` rejects as 400 problems.
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e: QueryPayloadError, _| {
+        Problem::new(StatusCode::BAD_REQUEST, e.to_string()).into()
+    })
+}
+
+/// Renders the requests `web::Path` rejects as 404 problems, as a path that does not
+/// deserialize names no resource.
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default()
+        .error_handler(|e: PathError, _| Problem::new(StatusCode::NOT_FOUND, e.to_string()).into())
+}
+
+/// Middleware giving every request an id: the one in its `X-Request-Id` header if that is a
+/// usable id, or a new UUID. The id is echoed in the `X-Request-Id` of the response, and problems
+/// rendered while the request is handled carry it as `request_id`, so a client can quote it.
+pub struct RequestIds;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIds
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestIdsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdsMiddleware { service }))
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(&X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| usable_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        // Handlers only run once the future is polled, which is inside the scope.
+        let response = self.service.call(req);
+        Box::pin(REQUEST_ID.scope(request_id.clone(), async move {
+            let mut response = response.await?;
+            let value = HeaderValue::from_str(&request_id).expect("request ids are ASCII");
+            response.headers_mut().insert(X_REQUEST_ID, value);
+            Ok(response)
+        }))
+    }
+}
+
+// Ids from clients end up in logs and responses, so only short, plain ones are kept.
+fn usable_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use actix_web::{error::BlockingError, web};
+use protocol::events::FruitEvent;
+
+use crate::rest::{Fruit, FruitSort};
+
+pub mod json_file;
+pub mod memory;
+pub mod sqlite;
+
+pub use json_file::JsonFileFruitRepository;
+pub use memory::InMemoryFruitRepository;
+pub use sqlite::SqliteFruitRepository;
+
+/// The highest id a fruit can have, so that ids fit the integers of every store. Once a fruit
+/// has this id, `create` has no ids left to assign and fails with `IdsExhausted`.
+pub const MAX_FRUIT_ID: u32 = i32::MAX as u32;
+
+/// Where fruits are kept. The REST handlers only see this trait; which implementation backs it
+/// is chosen by configuration when the gateway starts.
+///
+/// The methods may block on file or database I/O, so async code calls them through `blocking`.
+pub trait FruitRepository: Send + Sync {
+    fn get(&self, id: u32) -> Result<Option<Fruit>, RepositoryError>;
+
+    /// One page of the fruits matching the query, and how many match across all pages.
+    fn list(&self, query: &FruitQuery) -> Result<(Vec<Fruit>, usize), RepositoryError>;
+
+    /// Stores a new fruit, at version 1, under an id that has never been used.
+    ///
+    /// Fails with `IdsExhausted` once every id up to `MAX_FRUIT_ID` has been used.
+    fn create(&self, name: String) -> Result<Fruit, RepositoryError>;
+
+    /// Stores the fruit under its own id, replacing the fruit already there, if any, and returns
+    /// it as stored. The version of the fruit passed in is ignored: a replacement gets the next
+    /// version after the one it replaces, and so does a fruit put where one was deleted, so that
+    /// no precondition written against the deleted fruit holds for it. Any other new fruit gets
+    /// version 1.
+    ///
+    /// Fails with `PreconditionFailed`, changing nothing, unless `precondition` holds, and with
+    /// `IdOutOfRange` for an id above `MAX_FRUIT_ID`.
+    fn put(
+        &self,
+        fruit: Fruit,
+        precondition: &Precondition,
+    ) -> Result<(Saved, Fruit), RepositoryError>;
+
+    /// Removes the fruit and returns it as it was, or `None` if there was none.
+    ///
+    /// Fails with `PreconditionFailed`, changing nothing, unless `precondition` holds.
+    fn delete(
+        &self,
+        id: u32,
+        precondition: &Precondition,
+    ) -> Result<Option<Fruit>, RepositoryError>;
+
+    /// Makes all the writes, in order, or none of them: if one fails, nothing is changed.
+    fn batch(&self, writes: Vec<Write>) -> Result<Vec<Written>, BatchError>;
+
+    /// The oldest `limit` events in the outbox, where every write that changes a fruit records
+    /// a `FruitEvent` along with the change, until `remove_events` removes it.
+    fn pending_events(&self, limit: usize) -> Result<Vec<FruitEvent>, RepositoryError>;
+
+    /// Removes the events up to and including `sequence` from the outbox, once they have been
+    /// published.
+    fn remove_events(&self, sequence: u64) -> Result<(), RepositoryError>;
+}
+
+/// Calls `f` with the repository on the blocking thread pool. The file-based stores read and
+/// write their files while holding a lock, which would stall every request served by an async
+/// worker if they were called on it.
+pub async fn blocking<T, E, F>(repository: &Arc<dyn FruitRepository>, f: F) -> Result<T, E>
+where
+    F: FnOnce(&dyn FruitRepository) -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<RepositoryError> + Send + 'static,
+{
+    let repository = repository.clone();
+    web::block(move || f(repository.as_ref()))
+        .await
+        .unwrap_or_else(|e| Err(RepositoryError::Blocking(e).into()))
+}
+
+/// One of the writes of `FruitRepository::batch`, which behaves like the method of the same name.
+#[derive(Debug, Clone)]
+pub enum Write {
+    Create {
+        name: String,
+    },
+    Put {
+        fruit: Fruit,
+        precondition: Precondition,
+    },
+    Delete {
+        id: u32,
+        precondition: Precondition,
+    },
+}
+
+/// What one write of a batch did, as the method of the same name would have returned it.
+#[derive(Debug, Clone)]
+pub enum Written {
+    Created(Fruit),
+    Put(Saved, Fruit),
+    Deleted(Option<Fruit>),
+}
+
+/// Why a batch was not made.
+#[derive(Debug)]
+pub struct BatchError {
+    /// The position of the write that failed, or `None` if the batch as a whole failed.
+    pub index: Option<usize>,
+    pub error: RepositoryError,
+}
+
+impl From<RepositoryError> for BatchError {
+    fn from(error: RepositoryError) -> Self {
+        BatchError { index: None, error }
+    }
+}
+
+/// What a write expects of the fruit it changes, checked in the same step as the write so that
+/// concurrent writers cannot overwrite each other's changes unseen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// Whatever is there, if anything.
+    Unconditional,
+    /// The fruit exists, at any version.
+    Exists,
+    /// The fruit exists, at one of these versions.
+    Versions(Vec<u64>),
+}
+
+impl Precondition {
+    /// Whether the condition holds for a fruit at `current` version, or `None` for no fruit.
+    pub fn holds(&self, current: Option<u64>) -> bool {
+        match (self, current) {
+            (Precondition::Unconditional, _) => true,
+            (_, None) => false,
+            (Precondition::Exists, Some(_)) => true,
+            (Precondition::Versions(versions), Some(current)) => versions.contains(&current),
+        }
+    }
+
+    fn check(&self, id: u32, current: Option<u64>) -> Result<(), RepositoryError> {
+        if self.holds(current) {
+            Ok(())
+        } else {
+            Err(RepositoryError::PreconditionFailed { id })
+        }
+    }
+}
+
+/// Whether `FruitRepository::put` added a fruit or replaced one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Saved {
+    Created,
+    Replaced,
+}
+
+/// Which fruits `FruitRepository::list` returns, and in what order.
+#[derive(Debug, Clone, Default)]
+pub struct FruitQuery {
+    /// Only fruits whose name starts with this, ignoring case.
+    pub name_prefix: Option<String>,
+    /// Only fruits with a higher id than this, for paging through fruits in order of id by the
+    /// last id seen. Unlike the name prefix, it does not narrow the total.
+    pub after_id: Option<u32>,
+    pub sort: FruitSort,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// The fruit did not meet the `Precondition` of the write.
+    PreconditionFailed {
+        id: u32,
+    },
+    /// The id is above `MAX_FRUIT_ID`.
+    IdOutOfRange {
+        id: u32,
+    },
+    /// Every id up to `MAX_FRUIT_ID` has been used.
+    IdsExhausted,
+    /// The call could not be run on the blocking thread pool.
+    Blocking(BlockingError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::PreconditionFailed { id } => {
+                write!(f, "Fruit {id} does not meet the precondition of the write")
+            }
+            RepositoryError::IdOutOfRange { id } => {
+                write!(f, "Fruit id {id} is above the highest id, {MAX_FRUIT_ID}")
+            }
+            RepositoryError::IdsExhausted => write!(f, "Every fruit id has been used"),
+            RepositoryError::Blocking(e) => write!(f, "Could not call the store: {e}"),
+            RepositoryError::Io(e) => write!(f, "I/O error: {e}"),
+            RepositoryError::Json(e) => write!(f, "JSON error: {e}"),
+            RepositoryError::Sqlite(e) => write!(f, "SQLite error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl From<std::io::Error> for RepositoryError {
+    fn from(e: std::io::Error) -> Self {
+        RepositoryError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for RepositoryError {
+    fn from(e: serde_json::Error) -> Self {
+        RepositoryError::Json(e)
+    }
+}
+
+impl From<rusqlite::Error> for RepositoryError {
+    fn from(e: rusqlite::Error) -> Self {
+        RepositoryError::Sqlite(e)
+    }
+}
+
+// The id `create` assigns after `last_id`, the highest one used so far.
+pub(crate) fn next_id(last_id: u32) -> Result<u32, RepositoryError> {
+    last_id
+        .checked_add(1)
+        .filter(|id| *id <= MAX_FRUIT_ID)
+        .ok_or(RepositoryError::IdsExhausted)
+}
+
+pub(crate) fn check_id(id: u32) -> Result<(), RepositoryError> {
+    if id > MAX_FRUIT_ID {
+        return Err(RepositoryError::IdOutOfRange { id });
+    }
+    Ok(())
+}
+
+/// Fruits indexed by id, and the outbox of their changes, shared by the implementations that
+/// keep everything in memory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FruitTable {
+    // The highest id ever stored, so ids of deleted fruits are not handed out again.
+    last_id: u32,
+    fruits: HashMap<u32, Fruit>,
+    // The last version of every deleted fruit, which a fruit put at its id later carries on from.
+    // A PUT can store a fruit at any id, so no entry can be dropped before one is; there is one
+    // per id whose fruit is deleted, so never more than `last_id`, each smaller than a fruit.
+    deleted_versions: HashMap<u32, u64>,
+    // The sequence number of the last event recorded, and the events not yet published.
+    last_sequence: u64,
+    outbox: Vec<FruitEvent>,
+}
+
+impl FruitTable {
+    pub(crate) fn new(fruits: Vec<Fruit>, last_id: u32) -> Self {
+        let last_id = fruits.iter().map(|fruit| fruit.id).fold(last_id, u32::max);
+        let fruits = fruits.into_iter().map(|fruit| (fruit.id, fruit)).collect();
+        Self {
+            last_id,
+            fruits,
+            ..Self::default()
+        }
+    }
+
+    /// The table with the outbox as it was left.
+    pub(crate) fn with_outbox(self, outbox: Vec<FruitEvent>, last_sequence: u64) -> Self {
+        let last_sequence = outbox
+            .iter()
+            .map(|event| event.sequence)
+            .fold(last_sequence, u64::max);
+        Self {
+            last_sequence,
+            outbox,
+            ..self
+        }
+    }
+
+    /// The table with the versions of deleted fruits as they were left.
+    pub(crate) fn with_deleted_versions(self, deleted_versions: BTreeMap<u32, u64>) -> Self {
+        Self {
+            deleted_versions: deleted_versions.into_iter().collect(),
+            ..self
+        }
+    }
+
+    pub(crate) fn deleted_versions(&self) -> BTreeMap<u32, u64> {
+        self.deleted_versions
+            .iter()
+            .map(|(id, version)| (*id, *version))
+            .collect()
+    }
+
+    pub(crate) fn last_id(&self) -> u32 {
+        self.last_id
+    }
+
+    pub(crate) fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    pub(crate) fn pending_events(&self, limit: usize) -> Vec<FruitEvent> {
+        self.outbox.iter().take(limit).cloned().collect()
+    }
+
+    pub(crate) fn remove_events(&mut self, sequence: u64) {
+        self.outbox.retain(|event| event.sequence > sequence);
+    }
+
+    fn record(&mut self, before: Option<Fruit>, after: Option<Fruit>) {
+        if let Some(event) = FruitEvent::of_change(self.last_sequence + 1, before, after) {
+            self.last_sequence = event.sequence;
+            self.outbox.push(event);
+        }
+    }
+
+    /// Every fruit, in order of id.
+    pub(crate) fn fruits(&self) -> Vec<Fruit> {
+        let mut fruits: Vec<Fruit> = self.fruits.values().cloned().collect();
+        fruits.sort_by_key(|fruit| fruit.id);
+        fruits
+    }
+
+    pub(crate) fn get(&self, id: u32) -> Option<Fruit> {
+        self.fruits.get(&id).cloned()
+    }
+
+    pub(crate) fn list(&self, query: &FruitQuery) -> (Vec<Fruit>, usize) {
+        let prefix = query.name_prefix.as_deref().map(str::to_lowercase);
+        let mut fruits: Vec<&Fruit> = self
+            .fruits
+            .values()
+            .filter(|fruit| match &prefix {
+                Some(prefix) => fruit.name.to_lowercase().starts_with(prefix),
+                None => true,
+            })
+            .collect();
+        fruits.sort_by(|a, b| query.sort.compare(a, b));
+        let total = fruits.len();
+        let page = fruits
+            .into_iter()
+            .filter(|fruit| !matches!(query.after_id, Some(after_id) if fruit.id <= after_id))
+            .skip(query.offset)
+            .take(query.limit)
+            .cloned()
+            .collect();
+        (page, total)
+    }
+
+    pub(crate) fn create(&mut self, name: String) -> Result<Fruit, RepositoryError> {
+        self.last_id = next_id(self.last_id)?;
+        let fruit = Fruit {
+            id: self.last_id,
+            name,
+            version: 1,
+        };
+        self.fruits.insert(fruit.id, fruit.clone());
+        self.record(None, Some(fruit.clone()));
+        Ok(fruit)
+    }
+
+    pub(crate) fn put(
+        &mut self,
+        fruit: Fruit,
+        precondition: &Precondition,
+    ) -> Result<(Saved, Fruit), RepositoryError> {
+        check_id(fruit.id)?;
+        let current = self.fruits.get(&fruit.id).map(|fruit| fruit.version);
+        precondition.check(fruit.id, current)?;
+        let (saved, version) = match current {
+            Some(version) => (Saved::Replaced, version + 1),
+            None => match self.deleted_versions.remove(&fruit.id) {
+                Some(deleted) => (Saved::Created, deleted + 1),
+                None => (Saved::Created, 1),
+            },
+        };
+        let fruit = Fruit { version, ..fruit };
+        self.last_id = self.last_id.max(fruit.id);
+        let before = self.fruits.insert(fruit.id, fruit.clone());
+        self.record(before, Some(fruit.clone()));
+        Ok((saved, fruit))
+    }
+
+    pub(crate) fn delete(
+        &mut self,
+        id: u32,
+        precondition: &Precondition,
+    ) -> Result<Option<Fruit>, RepositoryError> {
+        precondition.check(id, self.fruits.get(&id).map(|fruit| fruit.version))?;
+        let before = self.fruits.remove(&id);
+        if let Some(fruit) = &before {
+            self.deleted_versions.insert(id, fruit.version);
+        }
+        self.record(before.clone(), None);
+        Ok(before)
+    }
+
+    // Callers make the batch on a copy of the table, which they drop if it fails part way.
+    pub(crate) fn batch(&mut self, writes: Vec<Write>) -> Result<Vec<Written>, BatchError> {
+        writes
+            .into_iter()
+            .enumerate()
+            .map(|(index, write)| {
+                let written = match write {
+                    Write::Create { name } => self.create(name).map(Written::Created),
+                    Write::Put {
+                        fruit,
+                        precondition,
+                    } => self
+                        .put(fruit, &precondition)
+                        .map(|(saved, fruit)| Written::Put(saved, fruit)),
+                    Write::Delete { id, precondition } => {
+                        self.delete(id, &precondition).map(Written::Deleted)
+                    }
+                };
+                written.map_err(|error| BatchError {
+                    index: Some(index),
+                    error,
+                })
+            })
+            .collect()
+    }
+}
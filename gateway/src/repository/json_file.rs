@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use protocol::events::FruitEvent;
+use serde::{Deserialize, Serialize};
+
+use crate::repository::{
+    BatchError, FruitQuery, FruitRepository, FruitTable, Precondition, RepositoryError, Saved,
+    Write, Written,
+};
+use crate::rest::Fruit;
+
+/// The contents of the file.
+#[derive(Serialize, Deserialize)]
+struct FruitFile {
+    last_id: u32,
+    fruits: Vec<Fruit>,
+    // Files written before fruit events were recorded have no outbox.
+    #[serde(default)]
+    last_sequence: u64,
+    #[serde(default)]
+    outbox: Vec<FruitEvent>,
+    // Nor the versions of deleted fruits: one entry, of two numbers, per id whose fruit is
+    // deleted, kept for as long as no fruit is put at the id.
+    #[serde(default)]
+    deleted_versions: BTreeMap<u32, u64>,
+}
+
+/// Keeps fruits in memory and writes all of them to a JSON file after every change.
+///
+/// Each change is applied to a copy of the fruits, and only becomes visible once the file has
+/// been written, so a failed write leaves both the file and the repository as they were.
+/// Every change rewrites the whole file, so this suits a small store; SQLite writes only what
+/// changed.
+pub struct JsonFileFruitRepository {
+    path: PathBuf,
+    table: Mutex<FruitTable>,
+}
+
+impl JsonFileFruitRepository {
+    /// Reads the fruits from `path`, or starts with `seed` if there is no file there yet.
+    pub fn open<P: AsRef<Path>>(path: P, seed: Vec<Fruit>) -> Result<Self, RepositoryError> {
+        let path = path.as_ref().to_path_buf();
+        let table = match fs::read(&path) {
+            Ok(contents) => {
+                let file: FruitFile = serde_json::from_slice(&contents)?;
+                FruitTable::new(file.fruits, file.last_id)
+                    .with_outbox(file.outbox, file.last_sequence)
+                    .with_deleted_versions(file.deleted_versions)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let table = FruitTable::new(seed, 0);
+                write(&path, &table)?;
+                table
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            table: Mutex::new(table),
+        })
+    }
+
+    fn change<T, E: From<RepositoryError>>(
+        &self,
+        change: impl FnOnce(&mut FruitTable) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut table = self.table.lock().unwrap();
+        let mut changed = table.clone();
+        let result = change(&mut changed)?;
+        write(&self.path, &changed)?;
+        *table = changed;
+        Ok(result)
+    }
+}
+
+// Writes to a temporary file first and renames it over the old one, so a crash mid-write
+// never leaves a truncated file behind. The temporary file is synced before the rename, or a
+// crash soon after it could leave the new name pointing at contents never written to disk.
+fn write(path: &Path, table: &FruitTable) -> Result<(), RepositoryError> {
+    let file = FruitFile {
+        last_id: table.last_id(),
+        fruits: table.fruits(),
+        last_sequence: table.last_sequence(),
+        outbox: table.pending_events(usize::MAX),
+        deleted_versions: table.deleted_versions(),
+    };
+    let temporary = path.with_extension("json.tmp");
+    let mut written = File::create(&temporary)?;
+    written.write_all(&serde_json::to_vec_pretty(&file)?)?;
+    written.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+impl FruitRepository for JsonFileFruitRepository {
+    fn get(&self, id: u32) -> Result<Option<Fruit>, RepositoryError> {
+        Ok(self.table.lock().unwrap().get(id))
+    }
+
+    fn list(&self, query: &FruitQuery) -> Result<(Vec<Fruit>, usize), RepositoryError> {
+        Ok(self.table.lock().unwrap().list(query))
+    }
+
+    fn create(&self, name: String) -> Result<Fruit, RepositoryError> {
+        self.change(|table| table.create(name))
+    }
+
+    fn put(
+        &self,
+        fruit: Fruit,
+        precondition: &Precondition,
+    ) -> Result<(Saved, Fruit), RepositoryError> {
+        self.change(|table| table.put(fruit, precondition))
+    }
+
+    fn delete(
+        &self,
+        id: u32,
+        precondition: &Precondition,
+    ) -> Result<Option<Fruit>, RepositoryError> {
+        self.change(|table| table.delete(id, precondition))
+    }
+
+    fn batch(&self, writes: Vec<Write>) -> Result<Vec<Written>, BatchError> {
+        self.change(|table| table.batch(writes))
+    }
+
+    fn pending_events(&self, limit: usize) -> Result<Vec<FruitEvent>, RepositoryError> {
+        Ok(self.table.lock().unwrap().pending_events(limit))
+    }
+
+    fn remove_events(&self, sequence: u64) -> Result<(), RepositoryError> {
+        self.change(|table| {
+            table.remove_events(sequence);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::Mutex;
+
+use protocol::events::FruitEvent;
+
+use crate::repository::{
+    BatchError, FruitQuery, FruitRepository, FruitTable, Precondition, RepositoryError, Saved,
+    Write, Written,
+};
+use crate::rest::Fruit;
+
+/// Keeps fruits in a map indexed by id. Everything is lost when the gateway stops.
+pub struct InMemoryFruitRepository {
+    table: Mutex<FruitTable>,
+}
+
+impl InMemoryFruitRepository {
+    pub fn new(fruits: Vec<Fruit>) -> Self {
+        Self {
+            table: Mutex::new(FruitTable::new(fruits, 0)),
+        }
+    }
+}
+
+impl FruitRepository for InMemoryFruitRepository {
+    fn get(&self, id: u32) -> Result<Option<Fruit>, RepositoryError> {
+        Ok(self.table.lock().unwrap().get(id))
+    }
+
+    fn list(&self, query: &FruitQuery) -> Result<(Vec<Fruit>, usize), RepositoryError> {
+        Ok(self.table.lock().unwrap().list(query))
+    }
+
+    fn create(&self, name: String) -> Result<Fruit, RepositoryError> {
+        self.table.lock().unwrap().create(name)
+    }
+
+    fn put(
+        &self,
+        fruit: Fruit,
+        precondition: &Precondition,
+    ) -> Result<(Saved, Fruit), RepositoryError> {
+        self.table.lock().unwrap().put(fruit, precondition)
+    }
+
+    fn delete(
+        &self,
+        id: u32,
+        precondition: &Precondition,
+    ) -> Result<Option<Fruit>, RepositoryError> {
+        self.table.lock().unwrap().delete(id, precondition)
+    }
+
+    fn batch(&self, writes: Vec<Write>) -> Result<Vec<Written>, BatchError> {
+        let mut table = self.table.lock().unwrap();
+        let mut changed = table.clone();
+        let written = changed.batch(writes)?;
+        *table = changed;
+        Ok(written)
+    }
+
+    fn pending_events(&self, limit: usize) -> Result<Vec<FruitEvent>, RepositoryError> {
+        Ok(self.table.lock().unwrap().pending_events(limit))
+    }
+
+    fn remove_events(&self, sequence: u64) -> Result<(), RepositoryError> {
+        self.table.lock().unwrap().remove_events(sequence);
+        Ok(())
+    }
+}
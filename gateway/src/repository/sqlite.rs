@@ -0,0 +1,313 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use protocol::events::FruitEvent;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::repository::{
+    check_id, BatchError, FruitQuery, FruitRepository, Precondition, RepositoryError, Saved, Write,
+    Written, MAX_FRUIT_ID,
+};
+use crate::rest::{Fruit, FruitSort};
+
+/// The steps that built the fruit schema, oldest first. A database counts the steps it has had
+/// in `PRAGMA user_version`, and opening the store runs the ones after those, so a released
+/// step is never changed: a new column or table is a new step.
+const MIGRATIONS: &[&str] = &[
+    // A plain rowid may go to a new fruit right after the fruit with the highest id is deleted,
+    // and a client still holding that id would then read or overwrite the new fruit.
+    "CREATE TABLE fruits (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL
+    )",
+    "ALTER TABLE fruits ADD COLUMN version INTEGER NOT NULL DEFAULT 1",
+    // The outbox. The fruits either side of a change are JSON, or NULL for no fruit; sequence
+    // numbers are not reused once the events are removed.
+    "CREATE TABLE fruit_events (
+        sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+        fruit_before TEXT,
+        fruit_after TEXT
+    )",
+    // The last version of each deleted fruit, until a fruit is put at its id again: a row per id
+    // whose fruit is deleted, so never more rows than ids handed out.
+    "CREATE TABLE deleted_fruits (
+        id INTEGER PRIMARY KEY,
+        version INTEGER NOT NULL
+    )",
+];
+
+/// Keeps fruits in an SQLite database.
+pub struct SqliteFruitRepository {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteFruitRepository {
+    /// Opens the database at `path`, creating it with `seed` in it if it is new.
+    pub fn open<P: AsRef<Path>>(path: P, seed: Vec<Fruit>) -> Result<Self, RepositoryError> {
+        Self::migrate(Connection::open(path)?, seed)
+    }
+
+    /// A database that lives only as long as the repository.
+    pub fn in_memory(seed: Vec<Fruit>) -> Result<Self, RepositoryError> {
+        Self::migrate(Connection::open_in_memory()?, seed)
+    }
+
+    fn migrate(mut connection: Connection, seed: Vec<Fruit>) -> Result<Self, RepositoryError> {
+        let transaction = connection.transaction()?;
+        let applied: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for migration in MIGRATIONS.iter().skip(applied) {
+            transaction.execute_batch(migration)?;
+        }
+        if applied == 0 {
+            for fruit in seed {
+                transaction.execute(
+                    "INSERT INTO fruits (id, name, version) VALUES (?1, ?2, ?3)",
+                    params![fruit.id, fruit.name, fruit.version],
+                )?;
+            }
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction.commit()?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+fn fruit_from_row(row: &Row) -> rusqlite::Result<Fruit> {
+    Ok(Fruit {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        version: row.get("version")?,
+    })
+}
+
+fn current(connection: &Connection, id: u32) -> rusqlite::Result<Option<Fruit>> {
+    connection
+        .query_row(
+            "SELECT id, name, version FROM fruits WHERE id = ?1",
+            [id],
+            fruit_from_row,
+        )
+        .optional()
+}
+
+fn order_by(sort: FruitSort) -> &'static str {
+    match sort {
+        FruitSort::IdAscending => "id",
+        FruitSort::IdDescending => "id DESC",
+        FruitSort::NameAscending => "name, id",
+        FruitSort::NameDescending => "name DESC, id",
+    }
+}
+
+impl FruitRepository for SqliteFruitRepository {
+    fn get(&self, id: u32) -> Result<Option<Fruit>, RepositoryError> {
+        Ok(current(&self.connection.lock().unwrap(), id)?)
+    }
+
+    fn list(&self, query: &FruitQuery) -> Result<(Vec<Fruit>, usize), RepositoryError> {
+        // A fruit matches when its name starts with the prefix. Cutting the name down to the
+        // length of the prefix, rather than matching `prefix || '%'` with `LIKE`, keeps a `%` or
+        // `_` in the prefix from acting as a wildcard: "50%" does not match "500 g".
+        let filter = "WHERE (?1 IS NULL OR lower(substr(name, 1, length(?1))) = lower(?1))";
+        let connection = self.connection.lock().unwrap();
+        let total: usize = connection.query_row(
+            &format!("SELECT count(*) FROM fruits {filter}"),
+            [&query.name_prefix],
+            |row| row.get(0),
+        )?;
+        let mut statement = connection.prepare(&format!(
+            "SELECT id, name, version FROM fruits {filter} AND (?4 IS NULL OR id > ?4)
+             ORDER BY {} LIMIT ?2 OFFSET ?3",
+            order_by(query.sort)
+        ))?;
+        let fruits = statement
+            .query_map(
+                params![query.name_prefix, query.limit, query.offset, query.after_id],
+                fruit_from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+        Ok((fruits, total))
+    }
+
+    fn create(&self, name: String) -> Result<Fruit, RepositoryError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let fruit = create(&transaction, name)?;
+        transaction.commit()?;
+        Ok(fruit)
+    }
+
+    fn put(
+        &self,
+        fruit: Fruit,
+        precondition: &Precondition,
+    ) -> Result<(Saved, Fruit), RepositoryError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let put = put(&transaction, fruit, precondition)?;
+        transaction.commit()?;
+        Ok(put)
+    }
+
+    fn delete(
+        &self,
+        id: u32,
+        precondition: &Precondition,
+    ) -> Result<Option<Fruit>, RepositoryError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let fruit = delete(&transaction, id, precondition)?;
+        transaction.commit()?;
+        Ok(fruit)
+    }
+
+    // A failed write returns before the commit, and dropping the transaction rolls back the
+    // writes before it.
+    fn batch(&self, writes: Vec<Write>) -> Result<Vec<Written>, BatchError> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(RepositoryError::from)?;
+        let written = writes
+            .into_iter()
+            .enumerate()
+            .map(|(index, write)| {
+                let written = match write {
+                    Write::Create { name } => create(&transaction, name).map(Written::Created),
+                    Write::Put {
+                        fruit,
+                        precondition,
+                    } => put(&transaction, fruit, &precondition)
+                        .map(|(saved, fruit)| Written::Put(saved, fruit)),
+                    Write::Delete { id, precondition } => {
+                        delete(&transaction, id, &precondition).map(Written::Deleted)
+                    }
+                };
+                written.map_err(|error| BatchError {
+                    index: Some(index),
+                    error,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        transaction.commit().map_err(RepositoryError::from)?;
+        Ok(written)
+    }
+
+    fn pending_events(&self, limit: usize) -> Result<Vec<FruitEvent>, RepositoryError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT sequence, fruit_before, fruit_after FROM fruit_events
+             ORDER BY sequence LIMIT ?1",
+        )?;
+        let rows = statement
+            .query_map([limit], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let fruit = |json: Option<String>| json.map(|json| serde_json::from_str(&json)).transpose();
+        let mut events = Vec::with_capacity(rows.len());
+        for (sequence, before, after) in rows {
+            events.extend(FruitEvent::of_change(
+                sequence,
+                fruit(before)?,
+                fruit(after)?,
+            ));
+        }
+        Ok(events)
+    }
+
+    fn remove_events(&self, sequence: u64) -> Result<(), RepositoryError> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM fruit_events WHERE sequence <= ?1", [sequence])?;
+        Ok(())
+    }
+}
+
+// The writes themselves, shared by the single writes and `batch`. Each writes the fruit and the
+// event of the change, and `put` and `delete` read before they write, so they run in a
+// transaction the caller opens.
+fn create(connection: &Connection, name: String) -> Result<Fruit, RepositoryError> {
+    connection.execute("INSERT INTO fruits (name, version) VALUES (?1, 1)", [&name])?;
+    // Past `MAX_FRUIT_ID` the insert is rolled back along with the rest of the transaction.
+    let id = u32::try_from(connection.last_insert_rowid())
+        .ok()
+        .filter(|id| *id <= MAX_FRUIT_ID)
+        .ok_or(RepositoryError::IdsExhausted)?;
+    let fruit = Fruit {
+        id,
+        name,
+        version: 1,
+    };
+    record(connection, None, Some(&fruit))?;
+    Ok(fruit)
+}
+
+fn put(
+    connection: &Connection,
+    fruit: Fruit,
+    precondition: &Precondition,
+) -> Result<(Saved, Fruit), RepositoryError> {
+    check_id(fruit.id)?;
+    let before = current(connection, fruit.id)?;
+    let current = before.as_ref().map(|fruit| fruit.version);
+    precondition.check(fruit.id, current)?;
+    let (saved, version) = match current {
+        Some(version) => (Saved::Replaced, version + 1),
+        None => {
+            let deleted: Option<u64> = connection
+                .query_row(
+                    "SELECT version FROM deleted_fruits WHERE id = ?1",
+                    [fruit.id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            connection.execute("DELETE FROM deleted_fruits WHERE id = ?1", [fruit.id])?;
+            (Saved::Created, deleted.map_or(1, |version| version + 1))
+        }
+    };
+    connection.execute(
+        "INSERT INTO fruits (id, name, version) VALUES (?1, ?2, ?3)
+         ON CONFLICT (id) DO UPDATE SET name = excluded.name, version = excluded.version",
+        params![fruit.id, fruit.name, version],
+    )?;
+    let fruit = Fruit { version, ..fruit };
+    record(connection, before.as_ref(), Some(&fruit))?;
+    Ok((saved, fruit))
+}
+
+fn delete(
+    connection: &Connection,
+    id: u32,
+    precondition: &Precondition,
+) -> Result<Option<Fruit>, RepositoryError> {
+    let fruit = current(connection, id)?;
+    precondition.check(id, fruit.as_ref().map(|fruit| fruit.version))?;
+    if let Some(deleted) = &fruit {
+        connection.execute("DELETE FROM fruits WHERE id = ?1", [id])?;
+        connection.execute(
+            "INSERT OR REPLACE INTO deleted_fruits (id, version) VALUES (?1, ?2)",
+            params![id, deleted.version],
+        )?;
+        record(connection, Some(deleted), None)?;
+    }
+    Ok(fruit)
+}
+
+fn record(
+    connection: &Connection,
+    before: Option<&Fruit>,
+    after: Option<&Fruit>,
+) -> Result<(), RepositoryError> {
+    let json = |fruit: Option<&Fruit>| fruit.map(serde_json::to_string).transpose();
+    connection.execute(
+        "INSERT INTO fruit_events (fruit_before, fruit_after) VALUES (?1, ?2)",
+        params![json(before)?, json(after)?],
+    )?;
+    Ok(())
+}
@@ -1,74 +1,1021 @@
-use std::sync::Mutex;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
-use actix_web::{delete, get, post, put, web, HttpResponse, Responder, Result};
+use actix_web::http::header::{
+    self, EntityTag, Header, HeaderName, HeaderValue, IfMatch, IfNoneMatch,
+};
+use actix_web::http::StatusCode;
+use actix_web::{
+    delete, get, patch, post, put, web, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+    Result,
+};
+use log::error;
+use protocol::validation::trimmed;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::formats::{single_record, Accepted, Decode, Decoded, Encode, Format, VARY_ACCEPT};
+use crate::problem::Problem;
+use crate::repository::{
+    blocking, BatchError, FruitQuery, FruitRepository, Precondition, RepositoryError, Saved, Write,
+    Written, MAX_FRUIT_ID,
+};
+
+const MAX_NAME_LENGTH: usize = 100;
+pub(crate) const DEFAULT_PAGE_LIMIT: usize = 20;
+pub(crate) const MAX_PAGE_LIMIT: usize = 100;
+const MAX_BATCH_SIZE: usize = 1000;
+// How often a PATCH reads and patches a fruit that others keep changing before it gives up.
+const MAX_PATCH_ATTEMPTS: usize = 3;
+
+/// The media types a PATCH body may have, as advertised in `Accept-Patch`.
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
 
 // Rest (https://learn.microsoft.com/en-us/azure/architecture/best-practices/api-design)
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
-pub struct Fruit {
-    pub id: u32,
+pub use protocol::models::Fruit;
+
+/// The body of a create request; the server assigns the id.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NewFruit {
     pub name: String,
 }
 
-#[derive(Serialize)]
-pub struct FruitList {
-    pub fruits: Mutex<Vec<Fruit>>,
+/// The query parameters of a list request.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FruitListQuery {
+    /// How many fruits to return, from 1 to 100. Defaults to 20.
+    pub limit: Option<usize>,
+    /// How many matching fruits to skip, at most 2147483647.
+    pub offset: Option<usize>,
+    pub sort: Option<FruitSort>,
+    /// Only fruits whose name starts with this, ignoring case.
+    pub name: Option<String>,
+}
+
+/// The order of a list of fruits, as given in the `sort` query parameter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum FruitSort {
+    #[default]
+    #[serde(rename = "id")]
+    IdAscending,
+    #[serde(rename = "-id")]
+    IdDescending,
+    #[serde(rename = "name")]
+    NameAscending,
+    #[serde(rename = "-name")]
+    NameDescending,
+}
+
+impl FruitSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FruitSort::IdAscending => "id",
+            FruitSort::IdDescending => "-id",
+            FruitSort::NameAscending => "name",
+            FruitSort::NameDescending => "-name",
+        }
+    }
+
+    // Fruits with the same name are ordered by id, so pages do not overlap.
+    pub(crate) fn compare(&self, a: &Fruit, b: &Fruit) -> Ordering {
+        match self {
+            FruitSort::IdAscending => a.id.cmp(&b.id),
+            FruitSort::IdDescending => b.id.cmp(&a.id),
+            FruitSort::NameAscending => a.name.cmp(&b.name).then(a.id.cmp(&b.id)),
+            FruitSort::NameDescending => b.name.cmp(&a.name).then(a.id.cmp(&b.id)),
+        }
+    }
+}
+
+/// One page of a list of fruits.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FruitPage {
+    pub items: Vec<Fruit>,
+    /// How many fruits match the filter, across all pages.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// The URL of the following page, or `None` on the last page.
+    pub next: Option<String>,
+}
+
+/// The body of a batch request.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FruitBatch {
+    /// Made in order, all of them or none; at most 1000.
+    pub operations: Vec<FruitOperation>,
+}
+
+/// One operation of a batch, which behaves like the request on a single fruit it is named after.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+pub enum FruitOperation {
+    /// Like `POST /fruits`.
+    Create { name: String },
+    /// Like `PUT /fruits/{id}`, with `if_version` in place of `If-Match`.
+    Put {
+        id: u32,
+        name: String,
+        if_version: Option<u64>,
+    },
+    /// Like `DELETE /fruits/{id}`, with `if_version` in place of `If-Match`.
+    Delete { id: u32, if_version: Option<u64> },
+}
+
+impl FruitOperation {
+    fn to_write(&self) -> Result<Write, FruitError> {
+        let only_at = |version: &Option<u64>, otherwise| match version {
+            Some(version) => Precondition::Versions(vec![*version]),
+            None => otherwise,
+        };
+        Ok(match self {
+            FruitOperation::Create { name } => Write::Create {
+                name: validate_name(name)?,
+            },
+            FruitOperation::Put {
+                id,
+                name,
+                if_version,
+            } => Write::Put {
+                fruit: Fruit {
+                    id: *id,
+                    name: validate_name(name)?,
+                    version: 0,
+                },
+                precondition: only_at(if_version, Precondition::Unconditional),
+            },
+            // Deleting a fruit that is not there fails the batch, as it would be 404 on its own.
+            FruitOperation::Delete { id, if_version } => Write::Delete {
+                id: *id,
+                precondition: only_at(if_version, Precondition::Exists),
+            },
+        })
+    }
+
+    // Why the write of this operation failed, as a request of its own would have reported it.
+    fn failure(&self, error: RepositoryError) -> FruitError {
+        match (self, error) {
+            (
+                FruitOperation::Delete {
+                    id,
+                    if_version: None,
+                },
+                RepositoryError::PreconditionFailed { .. },
+            ) => FruitError::NotFound { id: *id },
+            (_, error) => error.into(),
+        }
+    }
+}
+
+/// What one operation of a batch did, or why it was not made.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FruitOperationResult {
+    /// The status the operation would have had as a request of its own, or 424 Failed Dependency
+    /// for a valid operation that was not made because another one failed.
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fruit: Option<Fruit>,
+    /// Why the operation failed, as an RFC 7807 problem.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Problem>)]
+    pub problem: Option<serde_json::Value>,
+}
+
+impl FruitOperationResult {
+    fn new(status: StatusCode, fruit: Option<Fruit>) -> Self {
+        Self {
+            status: status.as_u16(),
+            fruit,
+            problem: None,
+        }
+    }
+}
+
+/// The response to a batch that was made.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FruitBatchResults {
+    /// One per operation, in the order of the operations.
+    pub results: Vec<FruitOperationResult>,
+}
+
+impl Encode for Fruit {
+    fn write_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> csv::Result<()> {
+        writer.serialize(self)
+    }
+}
+
+impl Decode for Fruit {
+    fn read_csv(reader: &mut csv::Reader<&[u8]>) -> Result<Self, String> {
+        single_record(reader)
+    }
+}
+
+impl Decode for NewFruit {
+    fn read_csv(reader: &mut csv::Reader<&[u8]>) -> Result<Self, String> {
+        single_record(reader)
+    }
+}
+
+// In CSV a page is the table of its fruits; the total and the next page are in the headers of
+// the response.
+impl Encode for FruitPage {
+    fn write_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> csv::Result<()> {
+        if self.items.is_empty() {
+            return writer.write_record(["id", "name", "version"]);
+        }
+        self.items
+            .iter()
+            .try_for_each(|fruit| writer.serialize(fruit))
+    }
+}
+
+// An operation of a batch as a flat record, the form it takes in CSV and XML. The columns an
+// operation does not take are left empty, and ignored.
+#[derive(Debug, Deserialize)]
+struct OperationRecord {
+    op: String,
+    id: Option<u32>,
+    name: Option<String>,
+    if_version: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OperationRecords {
+    #[serde(default)]
+    operations: Vec<OperationRecord>,
+}
+
+impl TryFrom<OperationRecord> for FruitOperation {
+    type Error = String;
+
+    fn try_from(record: OperationRecord) -> Result<Self, String> {
+        let missing = |field: &str| format!("a {} operation needs {field}", record.op);
+        match record.op.as_str() {
+            "create" => Ok(FruitOperation::Create {
+                name: record.name.ok_or_else(|| missing("name"))?,
+            }),
+            "put" => Ok(FruitOperation::Put {
+                id: record.id.ok_or_else(|| missing("id"))?,
+                name: record.name.ok_or_else(|| missing("name"))?,
+                if_version: record.if_version,
+            }),
+            "delete" => Ok(FruitOperation::Delete {
+                id: record.id.ok_or_else(|| missing("id"))?,
+                if_version: record.if_version,
+            }),
+            op => Err(format!("unknown op '{op}', expected create, put or delete")),
+        }
+    }
+}
+
+impl FruitBatch {
+    fn from_records(records: Vec<OperationRecord>) -> Result<Self, String> {
+        let operations = records
+            .into_iter()
+            .enumerate()
+            .map(|(index, record)| {
+                FruitOperation::try_from(record).map_err(|e| format!("operation {index}: {e}"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(FruitBatch { operations })
+    }
+}
+
+impl Decode for FruitBatch {
+    fn read_csv(reader: &mut csv::Reader<&[u8]>) -> Result<Self, String> {
+        let records = reader
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?;
+        FruitBatch::from_records(records)
+    }
+
+    // Read as flat records too, as quick-xml reads every value of a tagged enum as text, which
+    // then fails to read as an id.
+    fn read_xml(body: &str) -> Result<Self, String> {
+        let records: OperationRecords = quick_xml::de::from_str(body).map_err(|e| e.to_string())?;
+        FruitBatch::from_records(records.operations)
+    }
+}
+
+// A result of a batch as a flat record, the form it takes in CSV.
+#[derive(Debug, Serialize)]
+struct ResultRecord<'a> {
+    status: u16,
+    id: Option<u32>,
+    name: Option<&'a str>,
+    version: Option<u64>,
+}
+
+impl Encode for FruitBatchResults {
+    fn write_csv(&self, writer: &mut csv::Writer<Vec<u8>>) -> csv::Result<()> {
+        if self.results.is_empty() {
+            return writer.write_record(["status", "id", "name", "version"]);
+        }
+        self.results.iter().try_for_each(|result| {
+            let fruit = result.fruit.as_ref();
+            writer.serialize(ResultRecord {
+                status: result.status,
+                id: fruit.map(|fruit| fruit.id),
+                name: fruit.map(|fruit| fruit.name.as_str()),
+                version: fruit.map(|fruit| fruit.version),
+            })
+        })
+    }
+}
+
+/// Why a fruit request failed. Rendered as a `Problem`.
+#[derive(Debug)]
+pub enum FruitError {
+    /// `field` of the request has a value the API does not accept.
+    Invalid {
+        field: &'static str,
+        message: String,
+    },
+    /// The id in the body of a PUT differs from the id in its path.
+    IdMismatch {
+        path_id: u32,
+        body_id: u32,
+    },
+    NotFound {
+        id: u32,
+    },
+    /// The body of a PATCH is neither a merge patch nor a JSON patch.
+    UnsupportedPatch {
+        content_type: String,
+    },
+    /// The JSON patch cannot be applied to the fruit as it is, for example because a `test`
+    /// operation failed, or the fruit changed every time it was patched.
+    PatchConflict {
+        message: String,
+    },
+    /// The fruit does not match the `If-Match` header of the request.
+    PreconditionFailed {
+        id: u32,
+    },
+    /// Every id has been used, so no more fruits can be created.
+    IdsExhausted,
+    /// Some operations of a batch failed, so none were made. Takes the status of the first
+    /// failed operation.
+    BatchFailed {
+        status: StatusCode,
+        results: Vec<FruitOperationResult>,
+    },
+    /// The repository failed; the details are logged rather than sent to the client.
+    Storage(RepositoryError),
+}
+
+impl fmt::Display for FruitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FruitError::Invalid { field, message } => write!(f, "Invalid {field}: {message}"),
+            FruitError::IdMismatch { path_id, body_id } => write!(
+                f,
+                "The id in the body ({body_id}) does not match the id in the path ({path_id})"
+            ),
+            FruitError::NotFound { id } => write!(f, "There is no fruit with id {id}"),
+            FruitError::UnsupportedPatch { content_type } => write!(
+                f,
+                "A patch must be {MERGE_PATCH} or {JSON_PATCH}, not '{content_type}'"
+            ),
+            FruitError::PatchConflict { message } => {
+                write!(f, "The patch cannot be applied: {message}")
+            }
+            FruitError::PreconditionFailed { id } => write!(
+                f,
+                "Fruit {id} has changed since it was read; fetch it again and retry the change"
+            ),
+            FruitError::IdsExhausted => write!(
+                f,
+                "Every fruit id up to {MAX_FRUIT_ID} has been used, so no more fruits can be created"
+            ),
+            FruitError::BatchFailed { results, .. } => {
+                let failed = results
+                    .iter()
+                    .filter(|result| result.problem.is_some())
+                    .count();
+                write!(
+                    f,
+                    "None of the batch was made, because {failed} of its operations failed"
+                )
+            }
+            FruitError::Storage(_) => write!(f, "The fruit store is unavailable"),
+        }
+    }
+}
+
+impl ResponseError for FruitError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            FruitError::Invalid { .. } | FruitError::IdMismatch { .. } => StatusCode::BAD_REQUEST,
+            FruitError::NotFound { .. } => StatusCode::NOT_FOUND,
+            FruitError::UnsupportedPatch { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FruitError::PatchConflict { .. } | FruitError::IdsExhausted => StatusCode::CONFLICT,
+            FruitError::PreconditionFailed { .. } => StatusCode::PRECONDITION_FAILED,
+            FruitError::BatchFailed { status, .. } => *status,
+            FruitError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = self.problem().error_response();
+        if let FruitError::UnsupportedPatch { .. } = self {
+            response.headers_mut().insert(
+                HeaderName::from_static(ACCEPT_PATCH),
+                HeaderValue::from_static(ACCEPT_PATCH_VALUE),
+            );
+        }
+        response
+    }
+}
+
+impl FruitError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            FruitError::Invalid { .. } | FruitError::IdMismatch { .. } => "VALIDATION_ERROR",
+            FruitError::NotFound { .. } => "NOT_FOUND",
+            FruitError::UnsupportedPatch { .. } => "UNSUPPORTED_PATCH",
+            FruitError::PatchConflict { .. } => "PATCH_CONFLICT",
+            FruitError::PreconditionFailed { .. } => "PRECONDITION_FAILED",
+            FruitError::IdsExhausted => "IDS_EXHAUSTED",
+            FruitError::BatchFailed { .. } => "BATCH_FAILED",
+            FruitError::Storage(_) => "STORAGE_ERROR",
+        }
+    }
+
+    /// The problem the error is rendered as.
+    pub fn problem(&self) -> Problem {
+        let problem = Problem::new(self.status_code(), self.to_string());
+        match self {
+            FruitError::Invalid { field, .. } => problem.with("field", *field),
+            FruitError::BatchFailed { results, .. } => problem.with("results", json!(results)),
+            _ => problem,
+        }
+    }
+
+    // Fails a batch whose operations failed where `failures` holds an error, and would otherwise
+    // have been made.
+    fn batch_failed(failures: Vec<Option<FruitError>>) -> Self {
+        let mut status = None;
+        let results = failures
+            .into_iter()
+            .map(|failure| match failure {
+                Some(e) => {
+                    status.get_or_insert(e.status_code());
+                    FruitOperationResult {
+                        status: e.status_code().as_u16(),
+                        fruit: None,
+                        problem: Some(e.problem().body()),
+                    }
+                }
+                None => FruitOperationResult::new(StatusCode::FAILED_DEPENDENCY, None),
+            })
+            .collect();
+        FruitError::BatchFailed {
+            status: status.unwrap_or(StatusCode::FAILED_DEPENDENCY),
+            results,
+        }
+    }
+}
+
+impl From<RepositoryError> for FruitError {
+    fn from(e: RepositoryError) -> Self {
+        match e {
+            RepositoryError::PreconditionFailed { id } => FruitError::PreconditionFailed { id },
+            RepositoryError::IdOutOfRange { .. } => FruitError::Invalid {
+                field: "id",
+                message: format!("must be at most {MAX_FRUIT_ID}"),
+            },
+            RepositoryError::IdsExhausted => FruitError::IdsExhausted,
+            // Logged here, once, as the client is only told the store is unavailable.
+            e => {
+                error!("Fruit repository error: {e}");
+                FruitError::Storage(e)
+            }
+        }
+    }
+}
+
+/// The strong `ETag` of a fruit in a format: its version, such as `"3"`, in JSON, and the
+/// version and the name of the format, such as `"3-csv"`, in any other. Each format has its own
+/// bytes, so each needs its own tag.
+pub fn fruit_etag(fruit: &Fruit, format: Format) -> EntityTag {
+    match format {
+        Format::Json => EntityTag::new_strong(fruit.version.to_string()),
+        format => EntityTag::new_strong(format!("{}-{}", fruit.version, format.name())),
+    }
+}
+
+// The version in a tag made by `fruit_etag`, in whichever format.
+fn tagged_version(tag: &str) -> Option<u64> {
+    let version = match tag.split_once('-') {
+        Some((version, name)) if Format::ALL.iter().any(|format| format.name() == name) => version,
+        Some(_) => return None,
+        None => tag,
+    };
+    version.parse().ok()
+}
+
+// A strong `ETag` for any other body, from a hash of its bytes.
+fn body_etag(body: &[u8]) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    EntityTag::new_strong(format!("{:016x}", hasher.finish()))
+}
+
+// `Accept-Patch` (RFC 5789) is not among actix's named headers.
+const ACCEPT_PATCH: &str = "accept-patch";
+const ACCEPT_PATCH_VALUE: &str = "application/merge-patch+json, application/json-patch+json";
+
+// How many fruits match the filter of a list, for clients that cannot see the total in the body,
+// such as those reading the page as CSV.
+const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+// Whether `If-None-Match` lists `etag`, in which case a GET answers 304 Not Modified.
+// Compares weakly, as RFC 9110 requires for this header.
+fn not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    if !req.headers().contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+// What `If-Match` requires of the fruit a write changes. A tag of the fruit in any format will
+// do, as the write changes the fruit rather than one form of it. A header that lists no strong
+// tag a version could match, including one that does not parse, can never be met.
+fn write_precondition(req: &HttpRequest) -> Precondition {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Precondition::Unconditional;
+    }
+    match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => Precondition::Exists,
+        Ok(IfMatch::Items(tags)) => Precondition::Versions(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tagged_version(tag.tag()))
+                .collect(),
+        ),
+        Err(_) => Precondition::Versions(vec![]),
+    }
+}
+
+pub(crate) fn validate_name(name: &str) -> Result<String, FruitError> {
+    trimmed(name, MAX_NAME_LENGTH)
+        .map(str::to_string)
+        .map_err(|message| FruitError::Invalid {
+            field: "name",
+            message,
+        })
 }
 
 // Rest - get resource
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "fruits",
+    params(
+        ("id", description = "The id of the fruit"),
+        ("If-None-Match" = Option<String>, Header, description = "ETags of copies the client has")
+    ),
+    responses(
+        (status = 200, description = "The fruit", body = Fruit, headers(("ETag" = String))),
+        (status = 304, description = "The fruit still has one of the ETags in `If-None-Match`"),
+        (status = 404, description = "There is no such fruit", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[get("/fruits/{id}")]
-async fn get_fruit(fruit_id: web::Path<u32>, fruit_list: web::Data<FruitList>) -> HttpResponse {
+async fn get_fruit(
+    req: HttpRequest,
+    fruit_id: web::Path<u32>,
+    accepted: Accepted,
+    repository: web::Data<dyn FruitRepository>,
+) -> Result<HttpResponse> {
     let id = fruit_id.into_inner();
-    let maybe_fruit = fruit_list
-        .fruits
-        .lock()
-        .unwrap()
-        .iter()
-        .find(|&fruit| fruit.id == id)
-        .map(|fruit| fruit.clone());
-    maybe_fruit
-        .map(|fruit| HttpResponse::Ok().json(fruit))
-        .unwrap_or(HttpResponse::NotFound().finish())
-}
-
-// Rest - update resource
+    let fruit = blocking(&repository, move |repository| repository.get(id))
+        .await
+        .map_err(FruitError::from)?
+        .ok_or(FruitError::NotFound { id })?;
+    let etag = fruit_etag(&fruit, accepted.0);
+    if not_modified(&req, &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header(VARY_ACCEPT)
+            .finish());
+    }
+    let mut response = HttpResponse::Ok();
+    response.insert_header(header::ETag(etag));
+    Ok(accepted.respond(response, &fruit)?)
+}
+
+// Rest - create or replace resource, at the id in the path
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "fruits",
+    request_body = Fruit,
+    params(
+        ("id", description = "The id of the fruit, which must match the id in the body"),
+        ("If-Match" = Option<String>, Header, description = "Only replace a fruit with one of these ETags")
+    ),
+    responses(
+        (status = 200, description = "The fruit was replaced", body = Fruit, headers(("ETag" = String))),
+        (status = 201, description = "The fruit was created", body = Fruit, headers(("ETag" = String), ("Location" = String))),
+        (status = 400, description = "The name is invalid, the ids differ, or the id is too high", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The fruit does not match `If-Match`", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[put("/fruits/{id}")]
 async fn update_fruit(
-    fruit: web::Json<Fruit>,
-    fruit_list: web::Data<FruitList>,
-) -> Result<impl Responder> {
-    let mut fruits = fruit_list.fruits.lock().unwrap();
-    let maybe_fruit = fruits.iter_mut().find(|frt| frt.id == fruit.id);
-    match maybe_fruit {
-        Some(found_fruit) => (*found_fruit).name = fruit.into_inner().name,
-        None => fruits.push(fruit.into_inner()),
+    req: HttpRequest,
+    fruit_id: web::Path<u32>,
+    accepted: Accepted,
+    fruit: Decoded<Fruit>,
+    repository: web::Data<dyn FruitRepository>,
+) -> Result<HttpResponse> {
+    let id = fruit_id.into_inner();
+    if fruit.id != id {
+        return Err(FruitError::IdMismatch {
+            path_id: id,
+            body_id: fruit.id,
+        }
+        .into());
+    }
+    let fruit = Fruit {
+        id,
+        name: validate_name(&fruit.name)?,
+        version: 0,
+    };
+    let precondition = write_precondition(&req);
+    let (saved, fruit) = blocking(&repository, move |repository| {
+        repository.put(fruit, &precondition)
+    })
+    .await
+    .map_err(FruitError::from)?;
+    let mut response = match saved {
+        Saved::Replaced => HttpResponse::Ok(),
+        Saved::Created => {
+            let location = req.url_for("get_fruit", [id.to_string()])?;
+            let mut response = HttpResponse::Created();
+            response.insert_header((header::LOCATION, location.as_str()));
+            response
+        }
     };
-    Ok("".to_string())
+    response.insert_header(header::ETag(fruit_etag(&fruit, accepted.0)));
+    Ok(accepted.respond(response, &fruit)?)
+}
+
+// The patch body of a request, by its content type.
+enum FruitPatch {
+    Merge(serde_json::Value),
+    Json(json_patch::Patch),
+}
+
+impl FruitPatch {
+    fn parse(req: &HttpRequest, body: &[u8]) -> Result<Self, FruitError> {
+        let invalid = |e: serde_json::Error| FruitError::Invalid {
+            field: "body",
+            message: e.to_string(),
+        };
+        match req.content_type() {
+            MERGE_PATCH => Ok(FruitPatch::Merge(
+                serde_json::from_slice(body).map_err(invalid)?,
+            )),
+            JSON_PATCH => Ok(FruitPatch::Json(
+                serde_json::from_slice(body).map_err(invalid)?,
+            )),
+            content_type => Err(FruitError::UnsupportedPatch {
+                content_type: content_type.to_string(),
+            }),
+        }
+    }
+
+    // Patches the JSON form of the fruit and reads the result back, validated like a PUT body.
+    fn apply(&self, fruit: &Fruit) -> Result<Fruit, FruitError> {
+        let mut document = serde_json::to_value(fruit).expect("a fruit is always valid JSON");
+        match self {
+            FruitPatch::Merge(patch) => json_patch::merge(&mut document, patch),
+            FruitPatch::Json(patch) => {
+                json_patch::patch(&mut document, patch).map_err(|e| FruitError::PatchConflict {
+                    message: e.to_string(),
+                })?
+            }
+        }
+        let patched: Fruit = serde_json::from_value(document).map_err(|e| FruitError::Invalid {
+            field: "body",
+            message: format!("the patched fruit is not a fruit: {e}"),
+        })?;
+        if patched.id != fruit.id {
+            return Err(FruitError::IdMismatch {
+                path_id: fruit.id,
+                body_id: patched.id,
+            });
+        }
+        Ok(Fruit {
+            id: fruit.id,
+            name: validate_name(&patched.name)?,
+            version: fruit.version,
+        })
+    }
+}
+
+// Rest - partially update resource, with a JSON merge patch (RFC 7396) or a JSON patch (RFC 6902)
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "fruits",
+    request_body(
+        content = Object,
+        description = "A JSON merge patch, or a JSON patch as `application/json-patch+json`",
+        content_type = "application/merge-patch+json"
+    ),
+    params(
+        ("id", description = "The id of the fruit"),
+        ("If-Match" = Option<String>, Header, description = "Only patch a fruit with one of these ETags")
+    ),
+    responses(
+        (status = 200, description = "The patched fruit", body = Fruit, headers(("ETag" = String))),
+        (status = 400, description = "The patch, or the fruit it makes, is invalid", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "There is no such fruit", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "A JSON patch cannot be applied to the fruit, or the fruit kept changing while it was patched", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The fruit does not match `If-Match`", body = Problem, content_type = "application/problem+json"),
+        (status = 415, description = "The body is not a patch", body = Problem, content_type = "application/problem+json")
+    )
+)]
+#[patch("/fruits/{id}")]
+async fn patch_fruit(
+    req: HttpRequest,
+    fruit_id: web::Path<u32>,
+    accepted: Accepted,
+    body: web::Bytes,
+    repository: web::Data<dyn FruitRepository>,
+) -> Result<HttpResponse> {
+    let id = fruit_id.into_inner();
+    let patch = FruitPatch::parse(&req, &body)?;
+    let precondition = write_precondition(&req);
+    // The patch is applied to the fruit as read, and only stored if nobody has changed the fruit
+    // since; if somebody has, it is read and patched again, a few times at most.
+    for _ in 0..MAX_PATCH_ATTEMPTS {
+        let current = blocking(&repository, move |repository| repository.get(id))
+            .await
+            .map_err(FruitError::from)?
+            .ok_or(FruitError::NotFound { id })?;
+        if !precondition.holds(Some(current.version)) {
+            return Err(FruitError::PreconditionFailed { id }.into());
+        }
+        let patched = patch.apply(&current)?;
+        let unchanged = Precondition::Versions(vec![current.version]);
+        let put = blocking(&repository, move |repository| {
+            repository.put(patched, &unchanged)
+        });
+        match put.await {
+            Ok((_, fruit)) => {
+                let mut response = HttpResponse::Ok();
+                response.insert_header(header::ETag(fruit_etag(&fruit, accepted.0)));
+                return Ok(accepted.respond(response, &fruit)?);
+            }
+            Err(RepositoryError::PreconditionFailed { .. }) => continue,
+            Err(e) => return Err(FruitError::from(e).into()),
+        }
+    }
+    Err(FruitError::PatchConflict {
+        message: format!("fruit {id} kept changing while it was patched"),
+    }
+    .into())
 }
 
 // Rest - delete resource
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "fruits",
+    params(
+        ("id", description = "The id of the fruit"),
+        ("If-Match" = Option<String>, Header, description = "Only delete a fruit with one of these ETags")
+    ),
+    responses(
+        (status = 204, description = "The fruit was deleted"),
+        (status = 404, description = "There is no such fruit", body = Problem, content_type = "application/problem+json"),
+        (status = 412, description = "The fruit does not match `If-Match`", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[delete("/fruits/{id}")]
 async fn delete_fruit(
+    req: HttpRequest,
     fruit_id: web::Path<u32>,
-    fruit_list: web::Data<FruitList>,
-) -> Result<impl Responder> {
+    repository: web::Data<dyn FruitRepository>,
+) -> Result<HttpResponse> {
     let id = fruit_id.into_inner();
-    let mut fruits = fruit_list.fruits.lock().unwrap();
-    if let Some(pos) = fruits.iter_mut().position(|fruit| fruit.id == id) {
-        fruits.remove(pos);
-    }
-    Ok("".to_string())
+    let precondition = write_precondition(&req);
+    blocking(&repository, move |repository| {
+        repository.delete(id, &precondition)
+    })
+    .await
+    .map_err(FruitError::from)?
+    .ok_or(FruitError::NotFound { id })?;
+    Ok(HttpResponse::NoContent().finish())
 }
 
-// Rest - list resources
+// Rest - list resources, a page at a time
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "fruits",
+    params(
+        FruitListQuery,
+        ("If-None-Match" = Option<String>, Header, description = "ETags of copies the client has")
+    ),
+    responses(
+        (status = 200, description = "One page of fruits", body = FruitPage, headers(("ETag" = String))),
+        (status = 304, description = "The page still has one of the ETags in `If-None-Match`"),
+        (status = 400, description = "A query parameter is invalid", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[get("/fruits")]
-async fn get_fruits(fruit_list: web::Data<FruitList>) -> HttpResponse {
-    let fruits = fruit_list.fruits.lock().unwrap();
-    HttpResponse::Ok().json(fruits.clone())
+async fn get_fruits(
+    req: HttpRequest,
+    query: web::Query<FruitListQuery>,
+    accepted: Accepted,
+    repository: web::Data<dyn FruitRepository>,
+) -> Result<HttpResponse> {
+    let FruitListQuery {
+        limit,
+        offset,
+        sort,
+        name,
+    } = query.into_inner();
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(FruitError::Invalid {
+            field: "limit",
+            message: format!("must be between 1 and {MAX_PAGE_LIMIT}"),
+        }
+        .into());
+    }
+    // No store holds more fruits than there are ids, and an SQLite offset is at most `i64::MAX`.
+    let offset = offset.unwrap_or(0);
+    if offset > MAX_FRUIT_ID as usize {
+        return Err(FruitError::Invalid {
+            field: "offset",
+            message: format!("must be at most {MAX_FRUIT_ID}"),
+        }
+        .into());
+    }
+    let query = FruitQuery {
+        name_prefix: name,
+        after_id: None,
+        sort: sort.unwrap_or_default(),
+        offset,
+        limit,
+    };
+    let (items, total) = {
+        let query = query.clone();
+        blocking(&repository, move |repository| repository.list(&query))
+            .await
+            .map_err(FruitError::from)?
+    };
+
+    let next = if query.offset.saturating_add(limit) < total {
+        let mut next = req.url_for_static("get_fruits")?;
+        {
+            let mut pairs = next.query_pairs_mut();
+            pairs
+                .append_pair("limit", &limit.to_string())
+                .append_pair("offset", &(query.offset + limit).to_string())
+                .append_pair("sort", query.sort.as_str());
+            if let Some(name) = &query.name_prefix {
+                pairs.append_pair("name", name);
+            }
+        }
+        Some(next.to_string())
+    } else {
+        None
+    };
+    let page = FruitPage {
+        items,
+        total,
+        offset: query.offset,
+        limit,
+        next,
+    };
+    let body = accepted.0.encode(&page)?;
+    // The page changes whenever any fruit on it, or the set of fruits matching the filter,
+    // does, so its tag comes from the whole body rather than from versions.
+    let etag = body_etag(&body);
+    if not_modified(&req, &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header(VARY_ACCEPT)
+            .finish());
+    }
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header((X_TOTAL_COUNT, page.total));
+    if let Some(next) = &page.next {
+        response.insert_header((header::LINK, format!("<{next}>; rel=\"next\"")));
+    }
+    Ok(accepted.body(response, body))
 }
 
-// Rest - list resources
+// Rest - create resource
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "fruits",
+    request_body = NewFruit,
+    responses(
+        (status = 201, description = "The fruit, with the id it was given", body = Fruit, headers(("ETag" = String), ("Location" = String))),
+        (status = 400, description = "The name is invalid", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Every fruit id has been used", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[post("/fruits")]
-async fn create_fruit() -> Result<impl Responder> {
-    Ok("")
+async fn create_fruit(
+    req: HttpRequest,
+    accepted: Accepted,
+    new_fruit: Decoded<NewFruit>,
+    repository: web::Data<dyn FruitRepository>,
+) -> Result<HttpResponse> {
+    let name = validate_name(&new_fruit.name)?;
+    let fruit = blocking(&repository, move |repository| repository.create(name))
+        .await
+        .map_err(FruitError::from)?;
+    let location = req.url_for("get_fruit", [fruit.id.to_string()])?;
+    let mut response = HttpResponse::Created();
+    response
+        .insert_header((header::LOCATION, location.as_str()))
+        .insert_header(header::ETag(fruit_etag(&fruit, accepted.0)));
+    Ok(accepted.respond(response, &fruit)?)
+}
+
+// Rest - create, replace and delete many resources at once, all or nothing
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "fruits",
+    request_body = FruitBatch,
+    responses(
+        (status = 200, description = "Every operation was made", body = FruitBatchResults),
+        (status = "4XX", description = "None of the operations were made; the problem has the status of the first that failed, and `results` for each", body = Problem, content_type = "application/problem+json")
+    )
+)]
+#[post("/fruits:batch")]
+async fn batch_fruits(
+    accepted: Accepted,
+    batch: Decoded<FruitBatch>,
+    repository: web::Data<dyn FruitRepository>,
+) -> Result<HttpResponse> {
+    let operations = batch.into_inner().operations;
+    if operations.len() > MAX_BATCH_SIZE {
+        return Err(FruitError::Invalid {
+            field: "operations",
+            message: format!("must hold at most {MAX_BATCH_SIZE} operations"),
+        }
+        .into());
+    }
+    // Every operation is checked before any is made, so the client learns of all the invalid
+    // ones at once.
+    let mut writes = Vec::with_capacity(operations.len());
+    let mut failures = Vec::with_capacity(operations.len());
+    for operation in &operations {
+        match operation.to_write() {
+            Ok(write) => {
+                writes.push(write);
+                failures.push(None);
+            }
+            Err(e) => failures.push(Some(e)),
+        }
+    }
+    if failures.iter().any(Option::is_some) {
+        return Err(FruitError::batch_failed(failures).into());
+    }
+
+    let written = match blocking(&repository, move |repository| repository.batch(writes)).await {
+        Ok(written) => written,
+        Err(BatchError {
+            index: Some(index),
+            error,
+        }) => {
+            failures[index] = Some(operations[index].failure(error));
+            return Err(FruitError::batch_failed(failures).into());
+        }
+        Err(BatchError { index: None, error }) => return Err(FruitError::from(error).into()),
+    };
+    let results = written
+        .into_iter()
+        .map(|written| match written {
+            Written::Created(fruit) | Written::Put(Saved::Created, fruit) => {
+                FruitOperationResult::new(StatusCode::CREATED, Some(fruit))
+            }
+            Written::Put(Saved::Replaced, fruit) => {
+                FruitOperationResult::new(StatusCode::OK, Some(fruit))
+            }
+            Written::Deleted(_) => FruitOperationResult::new(StatusCode::NO_CONTENT, None),
+        })
+        .collect();
+    Ok(accepted.respond(HttpResponse::Ok(), &FruitBatchResults { results })?)
 }
@@ -0,0 +1,64 @@
+use actix_web::web;
+
+use crate::{
+    graphql::{graphql_post, graphql_ws, index_graphiql},
+    openapi::{api_docs, openapi_json},
+    problem::{json_config, path_config, query_config},
+    rest::{
+        batch_fruits, create_fruit, delete_fruit, get_fruit, get_fruits, patch_fruit, update_fruit,
+    },
+    simple::{
+        api_get_hello, api_get_hello_b, api_get_my_animal_result_responder, echo, hello,
+        post_with_body_deserialized,
+    },
+    v1::{api_v1_get_hello, api_v1_get_hello_b},
+    v2::{
+        api_v2_get_hello, api_v2_get_hello_b, api_v2_get_hello_b_query_params,
+        path_dynamic_segments, path_struct, path_struct_path_query,
+    },
+    web_socket::web_socket::index,
+};
+
+// Registers every route except the React App, which is served from files generated by the
+// binary's build script, and makes the extractors reject requests with problems.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(json_config())
+        .app_data(query_config())
+        .app_data(path_config())
+        .route("/ws/", web::get().to(index))
+        .service(hello)
+        .service(echo)
+        .service(api_get_my_animal_result_responder)
+        .service(post_with_body_deserialized)
+        .service(index_graphiql)
+        .service(graphql_post)
+        .service(graphql_ws)
+        .service(
+            web::scope("/api")
+                .service(openapi_json)
+                .service(api_docs)
+                .service(api_get_hello)
+                .service(api_get_hello_b)
+                .service(
+                    web::scope("/v1")
+                        .service(api_v1_get_hello)
+                        .service(api_v1_get_hello_b),
+                )
+                .service(
+                    web::scope("/v2")
+                        .service(api_v2_get_hello)
+                        .service(api_v2_get_hello_b)
+                        .service(api_v2_get_hello_b_query_params)
+                        .service(path_dynamic_segments)
+                        .service(path_struct)
+                        .service(path_struct_path_query)
+                        .service(get_fruit)
+                        .service(update_fruit)
+                        .service(patch_fruit)
+                        .service(delete_fruit)
+                        .service(get_fruits)
+                        .service(create_fruit)
+                        .service(batch_fruits),
+                ),
+        );
+}
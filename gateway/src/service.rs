@@ -0,0 +1,240 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::Addr;
+use actix_rt::time::timeout;
+use async_graphql::ErrorExtensions;
+use futures::channel::oneshot;
+use log::warn;
+use protocol::messages::{CommandError, ResponseMessageDto, ServiceQuery, ServiceRequest};
+use serde_json::json;
+use transport::Transport;
+use uuid::Uuid;
+
+use crate::actor::{GlobalActor, GlobalActorMessage};
+
+const REQUEST_TOPIC: &str = "from_router";
+const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Why a request to a backend service produced no usable reply.
+///
+/// Each variant is surfaced to GraphQL clients as an error with a `code` extension, the id of
+/// the request, and whether retrying the query could succeed.
+#[derive(Debug)]
+pub enum ServiceError {
+    /// No reply arrived within the reply window.
+    BackendTimeout { request_id: String },
+    /// So many requests were in flight that the gateway stopped waiting for this one.
+    Overloaded { request_id: String },
+    /// The request could not be handed to the transport.
+    BrokerUnavailable { request_id: String, reason: String },
+    /// A reply arrived but did not have the shape the request expects.
+    DecodeError { request_id: String, reason: String },
+    /// The gateway could not register the request with the `GlobalActor`.
+    Internal { request_id: String, reason: String },
+    /// The service understood the request but refused to apply it.
+    Rejected {
+        request_id: String,
+        error: CommandError,
+    },
+}
+
+impl ServiceError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServiceError::BackendTimeout { .. } => "BACKEND_TIMEOUT",
+            ServiceError::Overloaded { .. } => "GATEWAY_OVERLOADED",
+            ServiceError::BrokerUnavailable { .. } => "BROKER_UNAVAILABLE",
+            ServiceError::DecodeError { .. } => "DECODE_ERROR",
+            ServiceError::Internal { .. } => "INTERNAL_ERROR",
+            ServiceError::Rejected { error, .. } => error.code(),
+        }
+    }
+
+    pub fn request_id(&self) -> &str {
+        match self {
+            ServiceError::BackendTimeout { request_id }
+            | ServiceError::Overloaded { request_id }
+            | ServiceError::BrokerUnavailable { request_id, .. }
+            | ServiceError::DecodeError { request_id, .. }
+            | ServiceError::Internal { request_id, .. }
+            | ServiceError::Rejected { request_id, .. } => request_id,
+        }
+    }
+
+    /// Whether sending the same query again could succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ServiceError::BackendTimeout { .. }
+                | ServiceError::Overloaded { .. }
+                | ServiceError::BrokerUnavailable { .. }
+                | ServiceError::Rejected {
+                    error: CommandError::Storage { .. },
+                    ..
+                }
+        )
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::BackendTimeout { .. } => {
+                write!(f, "The backend service did not reply in time")
+            }
+            ServiceError::Overloaded { .. } => {
+                write!(
+                    f,
+                    "The gateway has too many requests in flight to wait for this one"
+                )
+            }
+            ServiceError::BrokerUnavailable { reason, .. } => {
+                write!(f, "The request could not be sent to the broker: {reason}")
+            }
+            ServiceError::DecodeError { reason, .. } => {
+                write!(
+                    f,
+                    "The backend service sent a reply that could not be read: {reason}"
+                )
+            }
+            ServiceError::Internal { reason, .. } => {
+                write!(f, "The request could not be registered: {reason}")
+            }
+            ServiceError::Rejected { error, .. } => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl ErrorExtensions for ServiceError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string()).extend_with(|_, e| {
+            e.set("code", self.code());
+            e.set("requestId", self.request_id());
+            e.set("retryable", self.is_retryable());
+            if let ServiceError::Rejected {
+                error: CommandError::Validation { field, .. },
+                ..
+            } = self
+            {
+                e.set("field", field.as_str());
+            }
+        })
+    }
+}
+
+/// Sends requests to the backend services over the transport and waits for their replies.
+///
+/// Every request names `reply_to`, the topic that only this gateway instance consumes, so the
+/// reply reaches the `GlobalActor` that registered the request even when several gateways run
+/// side by side.
+#[derive(Clone)]
+pub struct ServiceClient {
+    global_actor_address: Addr<GlobalActor>,
+    transport: Arc<dyn Transport>,
+    reply_to: String,
+    reply_timeout: Duration,
+}
+
+impl ServiceClient {
+    pub fn new(
+        global_actor_address: Addr<GlobalActor>,
+        transport: Arc<dyn Transport>,
+        reply_to: String,
+    ) -> Self {
+        Self {
+            global_actor_address,
+            transport,
+            reply_to,
+            reply_timeout: DEFAULT_REPLY_TIMEOUT,
+        }
+    }
+
+    /// Sets how long a request waits for its reply before failing with `BACKEND_TIMEOUT`.
+    pub fn with_reply_timeout(mut self, reply_timeout: Duration) -> Self {
+        self.reply_timeout = reply_timeout;
+        self
+    }
+
+    pub async fn request<Q: ServiceQuery>(&self, query: Q) -> Result<Q::Response, ServiceError> {
+        let (tx, rx) = oneshot::channel();
+        let request_id = Uuid::new_v4().to_string();
+        // Armed before the mapping is added, so a request dropped at any await below is forgotten.
+        let mut pending = Pending {
+            global_actor_address: self.global_actor_address.clone(),
+            request_id: request_id.clone(),
+            awaited: true,
+        };
+        self.global_actor_address
+            .send(GlobalActorMessage::AddMapping(request_id.clone(), tx))
+            .await
+            .map_err(|e| ServiceError::Internal {
+                request_id: request_id.clone(),
+                reason: e.to_string(),
+            })?;
+
+        let payload = json!(ServiceRequest {
+            request_id: request_id.clone(),
+            command: query.into(),
+            reply_to: Some(self.reply_to.clone()),
+        })
+        .to_string();
+        if let Err(e) = self
+            .transport
+            .publish(REQUEST_TOPIC, &request_id, &payload)
+            .await
+        {
+            return Err(ServiceError::BrokerUnavailable {
+                request_id,
+                reason: e.to_string(),
+            });
+        }
+
+        // Waits for the reply without blocking the worker thread, so other queries keep being
+        // served while this one is in flight.
+        let reply = timeout(self.reply_timeout, rx).await;
+        // The registry let go of the request when it passed on the reply or evicted the request.
+        pending.awaited = reply.is_err();
+        drop(pending);
+        let reply = match reply {
+            Ok(Ok(reply)) => reply,
+            // The sender is dropped when the registry evicts the request before a reply arrives.
+            Ok(Err(_)) => return Err(ServiceError::Overloaded { request_id }),
+            Err(_) => return Err(ServiceError::BackendTimeout { request_id }),
+        };
+        let reason = match reply {
+            Ok(ResponseMessageDto::Rejected { error }) => {
+                return Err(ServiceError::Rejected { request_id, error })
+            }
+            Ok(response) => match Q::from_response(response) {
+                Some(reply) => return Ok(reply),
+                None => "the reply does not answer this request".to_string(),
+            },
+            Err(reason) => reason,
+        };
+        warn!("Could not decode reply for request {request_id}: {reason}");
+        Err(ServiceError::DecodeError { request_id, reason })
+    }
+}
+
+// A request the registry holds a place for until its reply arrives. Dropping it while the reply
+// is still awaited, because the request failed, timed out, or its caller stopped waiting, frees
+// the place, so requests that get no reply do not push out the ones still waiting for theirs.
+struct Pending {
+    global_actor_address: Addr<GlobalActor>,
+    request_id: String,
+    awaited: bool,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.awaited {
+            let request_id = std::mem::take(&mut self.request_id);
+            self.global_actor_address
+                .do_send(GlobalActorMessage::RemoveMapping(request_id));
+        }
+    }
+}
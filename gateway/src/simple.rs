@@ -29,6 +29,11 @@ async fn post_with_body_deserialized(input: web::Json<Input>) -> Result<impl Res
     Ok(web::Json(output))
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "hello",
+    responses((status = 200, description = "A greeting", body = String, content_type = "text/plain"))
+)]
 #[get("/api-get")]
 async fn api_get_hello() -> impl Responder {
     HttpResponse::Ok().body("'api_get_hello'. scope: API, method: GET")
@@ -42,6 +47,11 @@ async fn api_get_my_animal_result_responder() -> Result<impl Responder> {
     }))
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "hello",
+    responses((status = 200, description = "A greeting", body = String, content_type = "text/plain"))
+)]
 #[get("/api-get-b")]
 async fn api_get_hello_b() -> impl Responder {
     HttpResponse::Ok().body("'api_get_hello_b'. scope: API, method: GET")
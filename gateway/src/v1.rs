@@ -1,10 +1,20 @@
 use actix_web::{get, HttpResponse, Responder};
 
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "hello",
+    responses((status = 200, description = "A greeting", body = String, content_type = "text/plain"))
+)]
 #[get("/api-v1-get")]
 async fn api_v1_get_hello() -> impl Responder {
     HttpResponse::Ok().body("'api_v1_get_hello'. scope: API, method: GET")
 }
 
+#[utoipa::path(
+    context_path = "/api/v1",
+    tag = "hello",
+    responses((status = 200, description = "A greeting", body = String, content_type = "text/plain"))
+)]
 #[get("/api-v1-get-b")]
 async fn api_v1_get_hello_b() -> impl Responder {
     HttpResponse::Ok().body("'api_v1_get_hello_b'. scope: API, method: GET")
@@ -2,16 +2,35 @@ use actix_web::{get, web, HttpResponse, Responder, Result};
 
 use crate::models::{Animal, Input};
 
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "hello",
+    responses((status = 200, description = "A greeting", body = String, content_type = "text/plain"))
+)]
 #[get("/api-v2-get")]
 async fn api_v2_get_hello() -> impl Responder {
     HttpResponse::Ok().body("'api_v2_get_hello'. scope: API, method: GET")
 }
 
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "hello",
+    responses((status = 200, description = "A greeting", body = String, content_type = "text/plain"))
+)]
 #[get("/api-v2-get-b")]
 async fn api_v2_get_hello_b() -> impl Responder {
     HttpResponse::Ok().body("'api_v2_get_hello_b'. scope: API, method: GET")
 }
 
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "hello",
+    params(Input),
+    responses(
+        (status = 200, description = "A greeting quoting the query", body = String, content_type = "text/plain"),
+        (status = 400, description = "`required_input` is missing", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[get("/api-v2-get-b-query-params")]
 async fn api_v2_get_hello_b_query_params(query_params: web::Query<Input>) -> impl Responder {
     HttpResponse::Ok().body(format!(
@@ -19,6 +38,18 @@ async fn api_v2_get_hello_b_query_params(query_params: web::Query<Input>) -> imp
         query_params.required_input, query_params.maybe_other_input.as_deref().unwrap_or("default")))
 }
 
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "animals",
+    params(
+        ("age" = u32, Path, description = "How old the animal is"),
+        ("animal" = String, Path, description = "What kind of animal it is")
+    ),
+    responses(
+        (status = 200, description = "The animal described", body = String, content_type = "text/plain"),
+        (status = 404, description = "`age` is not a number", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[get("/dynamic_segments/{age}/{animal}")]
 async fn path_dynamic_segments(path: web::Path<(u32, String)>) -> Result<String> {
     let (age, animal) = path.into_inner();
@@ -27,6 +58,15 @@ async fn path_dynamic_segments(path: web::Path<(u32, String)>) -> Result<String>
     ))
 }
 
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "animals",
+    params(Animal),
+    responses(
+        (status = 200, description = "The animal described", body = String, content_type = "text/plain"),
+        (status = 404, description = "`age` is not a number", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[get("/struct/{age}/{animal}")]
 async fn path_struct(animal: web::Path<Animal>) -> Result<String> {
     Ok(format!(
@@ -35,6 +75,16 @@ async fn path_struct(animal: web::Path<Animal>) -> Result<String> {
     ))
 }
 
+#[utoipa::path(
+    context_path = "/api/v2",
+    tag = "animals",
+    params(Animal, Input),
+    responses(
+        (status = 200, description = "The animal and the query described", body = String, content_type = "text/plain"),
+        (status = 400, description = "`required_input` is missing", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "`age` is not a number", body = Problem, content_type = "application/problem+json")
+    )
+)]
 #[get("/struct-path-query/{age}/{animal}")]
 async fn path_struct_path_query(
     animal: web::Path<Animal>,
@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod web_socket {
 
     use actix::{Actor, StreamHandler};
@@ -0,0 +1,202 @@
+// Each test crate compiles this module on its own and uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::Actor;
+use actix_web::{dev::ServiceResponse, test, web, App};
+use async_trait::async_trait;
+use gateway::{
+    actor::GlobalActor,
+    graphql::{build_schema, MySchema},
+    kafka_consumer::PersonChangeConsumer,
+    problem::RequestIds,
+    repository::{FruitRepository, InMemoryFruitRepository},
+    rest::Fruit,
+    routes::configure,
+    service::ServiceClient,
+};
+use protocol::events::PERSON_CHANGES_TOPIC;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use transport::{memory::InMemoryTransport, StartFrom, Subscription, Transport, TransportError};
+use user_service::{kafka_consumer::IngestConsumer, store::PersonStore};
+
+const REPLY_TOPIC: &str = "from_service.test";
+const REQUEST_TOPIC: &str = "from_router";
+
+/// Long enough for any reply the user-service sends in a test.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn in_memory_transport() -> Arc<dyn Transport> {
+    Arc::new(InMemoryTransport::new())
+}
+
+/// Forwards to another transport, counting the messages it is asked to publish, and failing the
+/// publishes after the first `up_for` as a broker that went down would.
+pub struct RecordingTransport {
+    transport: Arc<dyn Transport>,
+    published: AtomicUsize,
+    up_for: usize,
+}
+
+impl RecordingTransport {
+    pub fn new(transport: Arc<dyn Transport>) -> Self {
+        Self {
+            transport,
+            published: AtomicUsize::new(0),
+            up_for: usize::MAX,
+        }
+    }
+
+    /// Fails every publish after the first `up_for`.
+    pub fn failing_after(mut self, up_for: usize) -> Self {
+        self.up_for = up_for;
+        self
+    }
+
+    /// How many messages it was asked to publish, including those it failed.
+    pub fn published(&self) -> usize {
+        self.published.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Transport for RecordingTransport {
+    async fn publish(&self, topic: &str, key: &str, payload: &str) -> Result<(), TransportError> {
+        if self.published.fetch_add(1, Ordering::SeqCst) >= self.up_for {
+            return Err(TransportError::Backend("broker unavailable".to_string()));
+        }
+        self.transport.publish(topic, key, payload).await
+    }
+
+    async fn subscribe(
+        &self,
+        group_id: &str,
+        topics: &[&str],
+        start_from: StartFrom,
+    ) -> Result<Box<dyn Subscription>, TransportError> {
+        self.transport.subscribe(group_id, topics, start_from).await
+    }
+
+    async fn create_topic(&self, topic: &str) -> Result<(), TransportError> {
+        self.transport.create_topic(topic).await
+    }
+}
+
+/// What the gateway's `/graphql` route serves queries with: the schema, and the client each
+/// query's person loader sends its requests with.
+#[derive(Clone)]
+pub struct Gateway {
+    pub schema: MySchema,
+    pub service_client: ServiceClient,
+}
+
+/// Starts the gateway's consumers and returns what its routes serve, with no fruits.
+pub async fn start_gateway(transport: Arc<dyn Transport>, reply_timeout: Duration) -> Gateway {
+    let fruits = Arc::new(InMemoryFruitRepository::new(vec![]));
+    start_gateway_with_fruits(transport, reply_timeout, fruits).await
+}
+
+/// Like `start_gateway`, with the schema resolving fruits from `fruits`.
+pub async fn start_gateway_with_fruits(
+    transport: Arc<dyn Transport>,
+    reply_timeout: Duration,
+    fruits: Arc<dyn FruitRepository>,
+) -> Gateway {
+    let global_actor_address = GlobalActor::new().start();
+    let subscription = transport
+        .subscribe("gateway-test", &[REPLY_TOPIC], StartFrom::Earliest)
+        .await
+        .unwrap();
+    let mut ingest_consumer =
+        gateway::kafka_consumer::IngestConsumer::new(subscription, global_actor_address.clone());
+    actix_rt::spawn(async move { ingest_consumer.run().await });
+
+    let (person_changes, _) = broadcast::channel(16);
+    let subscription = transport
+        .subscribe("gateway-test", &[PERSON_CHANGES_TOPIC], StartFrom::Latest)
+        .await
+        .unwrap();
+    let mut person_change_consumer =
+        PersonChangeConsumer::new(subscription, person_changes.clone());
+    actix_rt::spawn(async move { person_change_consumer.run().await });
+
+    let service_client =
+        ServiceClient::new(global_actor_address, transport, REPLY_TOPIC.to_string())
+            .with_reply_timeout(reply_timeout);
+    Gateway {
+        schema: build_schema(service_client.clone(), person_changes, fruits),
+        service_client,
+    }
+}
+
+/// Starts the gateway and the user-service on one in-memory transport.
+pub async fn start() -> Gateway {
+    let transport = in_memory_transport();
+    start_user_service(transport.clone()).await;
+    start_gateway(transport, REPLY_TIMEOUT).await
+}
+
+/// Starts the user-service's request consumer on the same transport as the gateway.
+pub async fn start_user_service(transport: Arc<dyn Transport>) {
+    let subscription = transport
+        .subscribe("user-service", &[REQUEST_TOPIC], StartFrom::Earliest)
+        .await
+        .unwrap();
+    let mut ingest_consumer =
+        IngestConsumer::new(subscription, transport, PersonStore::in_memory().unwrap());
+    actix_rt::spawn(async move { ingest_consumer.run().await });
+}
+
+/// An in-memory fruit store holding `fruits`.
+pub fn repository_of(fruits: Vec<Fruit>) -> Arc<dyn FruitRepository> {
+    Arc::new(InMemoryFruitRepository::new(fruits))
+}
+
+/// The fruit the REST tests start from: a pear with id 5, at version 1.
+pub fn pear() -> Fruit {
+    Fruit {
+        id: 5,
+        name: "pear".to_string(),
+        version: 1,
+    }
+}
+
+/// A fruit store holding only `pear()`.
+pub fn repository() -> Arc<dyn FruitRepository> {
+    repository_of(vec![pear()])
+}
+
+/// Sends the request to the gateway's routes, which keep fruits in `repository`.
+pub async fn call(
+    repository: &Arc<dyn FruitRepository>,
+    req: test::TestRequest,
+) -> ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .wrap(RequestIds)
+            .app_data(web::Data::from(repository.clone()))
+            .configure(configure),
+    )
+    .await;
+    test::call_service(&app, req.to_request()).await
+}
+
+/// Posts the query to the gateway's `/graphql` route and returns the JSON response.
+pub async fn graphql(gateway: Gateway, query: &str) -> Value {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(gateway.schema))
+            .app_data(web::Data::new(gateway.service_client))
+            .configure(configure),
+    )
+    .await;
+    let req = test::TestRequest::post()
+        .uri("/graphql")
+        .set_json(json!({ "query": query }))
+        .to_request();
+    test::call_and_read_body_json(&app, req).await
+}
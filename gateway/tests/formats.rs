@@ -0,0 +1,385 @@
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{
+    dev::ServiceResponse,
+    http::{header, StatusCode},
+    test,
+};
+use gateway::{repository::FruitRepository, rest::Fruit};
+use serde_json::Value;
+
+use common::{call, pear, repository_of};
+
+// The pear, and a plum at a later version.
+fn pear_and_plum() -> Arc<dyn FruitRepository> {
+    let plum = Fruit {
+        id: 6,
+        name: "plum".to_string(),
+        version: 3,
+    };
+    repository_of(vec![pear(), plum])
+}
+
+fn get(uri: &str, accept: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(uri)
+        .insert_header((header::ACCEPT, accept))
+}
+
+fn send(
+    req: test::TestRequest,
+    uri: &str,
+    content_type: &str,
+    body: impl Into<Vec<u8>>,
+) -> test::TestRequest {
+    req.uri(uri)
+        .insert_header((header::CONTENT_TYPE, content_type))
+        .set_payload(body.into())
+}
+
+fn header(response: &ServiceResponse, name: header::HeaderName) -> &str {
+    response.headers().get(name).unwrap().to_str().unwrap()
+}
+
+async fn text(response: ServiceResponse) -> String {
+    String::from_utf8(test::read_body(response).await.to_vec()).unwrap()
+}
+
+#[actix_web::test]
+async fn fruit_is_json_without_accept() {
+    let response = call(
+        &pear_and_plum(),
+        test::TestRequest::get().uri("/api/v2/fruits/5"),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, header::CONTENT_TYPE), "application/json");
+    assert_eq!(header(&response, header::VARY), "accept");
+}
+
+#[actix_web::test]
+async fn fruit_as_csv() {
+    let response = call(&pear_and_plum(), get("/api/v2/fruits/5", "text/csv")).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, header::CONTENT_TYPE), "text/csv");
+    assert_eq!(header(&response, header::ETAG), "\"1-csv\"");
+    assert_eq!(text(response).await, "id,name,version\n5,pear,1\n");
+}
+
+#[actix_web::test]
+async fn fruit_as_msgpack() {
+    let response = call(
+        &pear_and_plum(),
+        get("/api/v2/fruits/6", "application/msgpack"),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header(&response, header::CONTENT_TYPE),
+        "application/msgpack"
+    );
+    let fruit: Fruit = rmp_serde::from_slice(&test::read_body(response).await).unwrap();
+    assert_eq!(
+        (fruit.id, fruit.name.as_str(), fruit.version),
+        (6, "plum", 3)
+    );
+}
+
+#[actix_web::test]
+async fn fruit_as_xml() {
+    let response = call(&pear_and_plum(), get("/api/v2/fruits/5", "application/xml")).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, header::CONTENT_TYPE), "application/xml");
+    assert_eq!(
+        text(response).await,
+        "<Fruit><id>5</id><name>pear</name><version>1</version></Fruit>"
+    );
+}
+
+#[actix_web::test]
+async fn accept_picks_the_preferred_format() {
+    let repository = pear_and_plum();
+    for (accept, content_type) in [
+        ("application/json;q=0.5, text/csv", "text/csv"),
+        (
+            "text/html, application/x-msgpack;q=0.9, */*;q=0.8",
+            "application/msgpack",
+        ),
+        ("text/*", "text/csv"),
+        ("application/*", "application/json"),
+        ("*/*", "application/json"),
+        ("application/json;q=0, */*", "text/csv"),
+        ("text/xml", "application/xml"),
+    ] {
+        let response = call(&repository, get("/api/v2/fruits/5", accept)).await;
+
+        assert_eq!(response.status(), StatusCode::OK, "{accept}");
+        assert_eq!(
+            header(&response, header::CONTENT_TYPE),
+            content_type,
+            "{accept}"
+        );
+    }
+}
+
+#[actix_web::test]
+async fn unsupported_accept_is_not_acceptable() {
+    let repository = pear_and_plum();
+
+    let response = call(&repository, get("/api/v2/fruits/5", "image/png")).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(
+        header(&response, header::CONTENT_TYPE),
+        "application/problem+json"
+    );
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["status"], 406);
+
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits",
+        "application/json",
+        r#"{ "name": "fig" }"#,
+    )
+    .insert_header((header::ACCEPT, "text/html"));
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    let response = call(&repository, get("/api/v2/fruits/7", "*/*")).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn list_as_csv_has_the_total_and_next_page_in_headers() {
+    let response = call(&pear_and_plum(), get("/api/v2/fruits?limit=1", "text/csv")).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "x-total-count".parse().unwrap()), "2");
+    assert_eq!(
+        header(&response, header::LINK),
+        "<http://localhost:8080/api/v2/fruits?limit=1&offset=1&sort=id>; rel=\"next\""
+    );
+    assert_eq!(text(response).await, "id,name,version\n5,pear,1\n");
+}
+
+#[actix_web::test]
+async fn empty_list_as_csv_is_a_header_row() {
+    let response = call(&pear_and_plum(), get("/api/v2/fruits?name=x", "text/csv")).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "id,name,version\n");
+}
+
+#[actix_web::test]
+async fn fruit_has_an_etag_per_format() {
+    let repository = pear_and_plum();
+
+    let json = call(&repository, get("/api/v2/fruits/5", "application/json")).await;
+    let csv = call(&repository, get("/api/v2/fruits/5", "text/csv")).await;
+    assert_eq!(header(&json, header::ETAG), "\"1\"");
+    assert_eq!(header(&csv, header::ETAG), "\"1-csv\"");
+
+    // A copy in one format does not stand in for another.
+    let req = get("/api/v2/fruits/5", "application/json")
+        .insert_header((header::IF_NONE_MATCH, "\"1-csv\""));
+    assert_eq!(call(&repository, req).await.status(), StatusCode::OK);
+    let req =
+        get("/api/v2/fruits/5", "text/csv").insert_header((header::IF_NONE_MATCH, "\"1-csv\""));
+    assert_eq!(
+        call(&repository, req).await.status(),
+        StatusCode::NOT_MODIFIED
+    );
+
+    // But a write may be conditional on the tag of any of them.
+    let req = send(
+        test::TestRequest::put(),
+        "/api/v2/fruits/5",
+        "text/csv",
+        "id,name\n5,nashi\n",
+    )
+    .insert_header((header::ACCEPT, "text/csv"))
+    .insert_header((header::IF_MATCH, "\"1-csv\""));
+    let response = call(&repository, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, header::ETAG), "\"2-csv\"");
+    let req = send(
+        test::TestRequest::put(),
+        "/api/v2/fruits/5",
+        "application/json",
+        r#"{"id":5,"name":"conference"}"#,
+    )
+    .insert_header((header::IF_MATCH, "\"1-xml\""));
+    let response = call(&repository, req).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+#[actix_web::test]
+async fn list_has_an_etag_per_format() {
+    let repository = pear_and_plum();
+
+    let json = call(&repository, get("/api/v2/fruits", "application/json")).await;
+    let csv = call(&repository, get("/api/v2/fruits", "text/csv")).await;
+    assert_ne!(header(&json, header::ETAG), header(&csv, header::ETAG));
+
+    let etag = header(&csv, header::ETAG).to_string();
+    let req = get("/api/v2/fruits", "text/csv").insert_header((header::IF_NONE_MATCH, etag));
+    let response = call(&repository, req).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(header(&response, header::VARY), "accept");
+}
+
+#[actix_web::test]
+async fn fruit_is_created_from_csv() {
+    let repository = pear_and_plum();
+
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits",
+        "text/csv",
+        "name\nfig\n",
+    )
+    .insert_header((header::ACCEPT, "text/csv"));
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(text(response).await, "id,name,version\n7,fig,1\n");
+}
+
+#[actix_web::test]
+async fn fruit_is_created_from_msgpack() {
+    let repository = pear_and_plum();
+    let body = rmp_serde::to_vec_named(&serde_json::json!({ "name": "fig" })).unwrap();
+
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits",
+        "application/x-msgpack",
+        body,
+    );
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["name"], "fig");
+}
+
+#[actix_web::test]
+async fn fruit_is_replaced_from_xml() {
+    let repository = pear_and_plum();
+
+    let req = send(
+        test::TestRequest::put(),
+        "/api/v2/fruits/5",
+        "application/xml",
+        "<Fruit><id>5</id><name>nashi</name></Fruit>",
+    )
+    .insert_header((header::ACCEPT, "application/xml"));
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        text(response).await,
+        "<Fruit><id>5</id><name>nashi</name><version>2</version></Fruit>"
+    );
+}
+
+#[actix_web::test]
+async fn body_of_an_unsupported_type_is_a_problem() {
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits",
+        "application/yaml",
+        "name: fig",
+    );
+
+    let response = call(&pear_and_plum(), req).await;
+
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(
+        header(&response, header::CONTENT_TYPE),
+        "application/problem+json"
+    );
+}
+
+#[actix_web::test]
+async fn csv_body_with_more_than_one_fruit_is_invalid() {
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits",
+        "text/csv",
+        "name\nfig\nkiwi\n",
+    );
+
+    let response = call(&pear_and_plum(), req).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "body");
+}
+
+#[actix_web::test]
+async fn batch_from_csv_answers_in_csv() {
+    let repository = pear_and_plum();
+
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits:batch",
+        "text/csv",
+        "op,id,name,if_version\ncreate,,fig,\nput,5,nashi,1\ndelete,6,,\n",
+    )
+    .insert_header((header::ACCEPT, "text/csv"));
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        text(response).await,
+        "status,id,name,version\n201,7,fig,1\n200,5,nashi,2\n204,,,\n"
+    );
+}
+
+#[actix_web::test]
+async fn batch_from_xml() {
+    let repository = pear_and_plum();
+
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits:batch",
+        "application/xml",
+        "<FruitBatch>\
+            <operations><op>create</op><name>fig</name></operations>\
+            <operations><op>delete</op><id>6</id><if_version>3</if_version></operations>\
+        </FruitBatch>",
+    );
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["results"][0]["status"], 201);
+    assert_eq!(body["results"][1]["status"], 204);
+}
+
+#[actix_web::test]
+async fn batch_operation_missing_a_column_is_invalid() {
+    let req = send(
+        test::TestRequest::post(),
+        "/api/v2/fruits:batch",
+        "text/csv",
+        "op,id,name,if_version\nput,5,,\n",
+    );
+
+    let response = call(&pear_and_plum(), req).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert!(body["detail"]
+        .as_str()
+        .unwrap()
+        .contains("operation 0: a put operation needs name"));
+}
@@ -0,0 +1,739 @@
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{
+    http::{header, StatusCode},
+    test,
+};
+use futures::future::join_all;
+use gateway::{
+    repository::{
+        BatchError, FruitQuery, FruitRepository, Precondition, RepositoryError, Saved,
+        SqliteFruitRepository, Write, Written, MAX_FRUIT_ID,
+    },
+    rest::Fruit,
+};
+use protocol::events::FruitEvent;
+use serde_json::{json, Value};
+
+use common::{call, pear, repository, repository_of};
+
+fn all(repository: &Arc<dyn FruitRepository>) -> Vec<Fruit> {
+    let query = FruitQuery {
+        limit: usize::MAX,
+        ..FruitQuery::default()
+    };
+    repository.list(&query).unwrap().0
+}
+
+fn create(body: Value) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/api/v2/fruits")
+        .set_json(body)
+}
+
+#[actix_web::test]
+async fn created_fruit_gets_a_new_id_and_a_location() {
+    let repository = repository();
+
+    let response = call(&repository, create(json!({ "name": " plum " }))).await;
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "http://localhost:8080/api/v2/fruits/6"
+    );
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body, json!({ "id": 6, "name": "plum", "version": 1 }));
+
+    let response = call(
+        &repository,
+        test::TestRequest::get().uri("/api/v2/fruits/6"),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn concurrent_creates_get_distinct_ids() {
+    let repository = repository();
+
+    let responses = join_all(
+        (0..20).map(|i| call(&repository, create(json!({ "name": format!("fruit {i}") })))),
+    )
+    .await;
+
+    let mut ids = Vec::new();
+    for response in responses {
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let fruit: Fruit = test::read_body_json(response).await;
+        ids.push(fruit.id);
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    assert_eq!(ids.len(), 20);
+    assert_eq!(all(&repository).len(), 21);
+}
+
+#[actix_web::test]
+async fn blank_name_is_rejected() {
+    let repository = repository();
+
+    let response = call(&repository, create(json!({ "name": "  " }))).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "name");
+    assert_eq!(all(&repository).len(), 1);
+}
+
+#[actix_web::test]
+async fn client_chosen_id_is_rejected() {
+    let repository = repository();
+
+    let response = call(&repository, create(json!({ "id": 7, "name": "plum" }))).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(all(&repository).len(), 1);
+}
+
+fn put(path_id: u32, body: Value) -> test::TestRequest {
+    test::TestRequest::put()
+        .uri(&format!("/api/v2/fruits/{path_id}"))
+        .set_json(body)
+}
+
+#[actix_web::test]
+async fn put_replaces_an_existing_fruit() {
+    let repository = repository();
+
+    let response = call(&repository, put(5, json!({ "id": 5, "name": "pineapple" }))).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body, json!({ "id": 5, "name": "pineapple", "version": 2 }));
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "pineapple");
+}
+
+#[actix_web::test]
+async fn put_creates_a_fruit_at_the_path_id() {
+    let repository = repository();
+
+    let response = call(&repository, put(559, json!({ "id": 559, "name": "plum" }))).await;
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "http://localhost:8080/api/v2/fruits/559"
+    );
+    assert_eq!(all(&repository).len(), 2);
+}
+
+#[actix_web::test]
+async fn ids_run_out_instead_of_wrapping_around() {
+    let repository = repository();
+
+    let response = call(
+        &repository,
+        put(u32::MAX, json!({ "id": u32::MAX, "name": "fig" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "id");
+
+    let last = put(MAX_FRUIT_ID, json!({ "id": MAX_FRUIT_ID, "name": "fig" }));
+    assert_eq!(call(&repository, last).await.status(), StatusCode::CREATED);
+    let response = call(&repository, create(json!({ "name": "date" }))).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(all(&repository).len(), 2);
+    let list = test::TestRequest::get().uri("/api/v2/fruits");
+    assert_eq!(call(&repository, list).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn put_with_a_different_body_id_is_a_problem() {
+    let repository = repository();
+
+    let response = call(&repository, put(5, json!({ "id": 559, "name": "plum" }))).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/problem+json"
+    );
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], "Bad Request");
+    assert_eq!(body["status"], 400);
+    assert_eq!(
+        body["detail"],
+        "The id in the body (559) does not match the id in the path (5)"
+    );
+    let fruits = all(&repository);
+    assert_eq!(fruits.len(), 1);
+    assert_eq!(fruits[0].name, "pear");
+}
+
+#[actix_web::test]
+async fn delete_removes_the_fruit() {
+    let repository = repository();
+
+    let response = call(
+        &repository,
+        test::TestRequest::delete().uri("/api/v2/fruits/5"),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(all(&repository).is_empty());
+}
+
+#[actix_web::test]
+async fn delete_of_a_missing_fruit_is_not_found() {
+    let repository = repository();
+
+    let response = call(
+        &repository,
+        test::TestRequest::delete().uri("/api/v2/fruits/6"),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["detail"], "There is no fruit with id 6");
+}
+
+fn get(id: u32) -> test::TestRequest {
+    test::TestRequest::get().uri(&format!("/api/v2/fruits/{id}"))
+}
+
+#[actix_web::test]
+async fn fruit_carries_its_version_as_a_strong_etag() {
+    let repository = repository();
+
+    let response = call(&repository, get(5)).await;
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"1\"");
+
+    let response = call(&repository, put(5, json!({ "id": 5, "name": "nashi" }))).await;
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"2\"");
+    let response = call(&repository, get(5)).await;
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"2\"");
+}
+
+#[actix_web::test]
+async fn get_with_a_current_if_none_match_is_not_modified() {
+    let repository = repository();
+
+    let response = call(
+        &repository,
+        get(5).insert_header((header::IF_NONE_MATCH, "\"1\"")),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"1\"");
+
+    let response = call(
+        &repository,
+        get(5).insert_header((header::IF_NONE_MATCH, "\"0\"")),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn put_with_a_stale_if_match_is_refused() {
+    let repository = repository();
+    let first =
+        put(5, json!({ "id": 5, "name": "nashi" })).insert_header((header::IF_MATCH, "\"1\""));
+    let second =
+        put(5, json!({ "id": 5, "name": "conference" })).insert_header((header::IF_MATCH, "\"1\""));
+
+    assert_eq!(call(&repository, first).await.status(), StatusCode::OK);
+    let response = call(&repository, second).await;
+
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/problem+json"
+    );
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "nashi");
+}
+
+#[actix_web::test]
+async fn if_match_needs_a_strong_tag_of_an_existing_fruit() {
+    let repository = repository();
+
+    for if_match in ["W/\"1\"", "not a tag"] {
+        let req =
+            put(5, json!({ "id": 5, "name": "nashi" })).insert_header((header::IF_MATCH, if_match));
+        assert_eq!(
+            call(&repository, req).await.status(),
+            StatusCode::PRECONDITION_FAILED
+        );
+    }
+    let req = put(6, json!({ "id": 6, "name": "plum" })).insert_header((header::IF_MATCH, "*"));
+    assert_eq!(
+        call(&repository, req).await.status(),
+        StatusCode::PRECONDITION_FAILED
+    );
+    let fruits = all(&repository);
+    assert_eq!(fruits.len(), 1);
+    assert_eq!((fruits[0].name.as_str(), fruits[0].version), ("pear", 1));
+}
+
+#[actix_web::test]
+async fn delete_honours_if_match() {
+    let repository = repository();
+    let delete = |if_match: &str| {
+        test::TestRequest::delete()
+            .uri("/api/v2/fruits/5")
+            .insert_header((header::IF_MATCH, if_match.to_string()))
+    };
+
+    let response = call(&repository, delete("\"2\"")).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(all(&repository).len(), 1);
+
+    let response = call(&repository, delete("\"2\", \"1\"")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(all(&repository).is_empty());
+}
+
+#[actix_web::test]
+async fn if_match_of_a_deleted_fruit_does_not_match_its_successor() {
+    let repository = repository();
+    let delete = test::TestRequest::delete().uri("/api/v2/fruits/5");
+    assert_eq!(
+        call(&repository, delete).await.status(),
+        StatusCode::NO_CONTENT
+    );
+
+    let response = call(&repository, put(5, json!({ "id": 5, "name": "nashi" }))).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"2\"");
+
+    let stale =
+        put(5, json!({ "id": 5, "name": "conference" })).insert_header((header::IF_MATCH, "\"1\""));
+    let response = call(&repository, stale).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "nashi");
+}
+
+fn patch(id: u32, content_type: &str, body: Value) -> test::TestRequest {
+    test::TestRequest::patch()
+        .uri(&format!("/api/v2/fruits/{id}"))
+        .insert_header((header::CONTENT_TYPE, content_type.to_string()))
+        .set_payload(body.to_string())
+}
+
+#[actix_web::test]
+async fn merge_patch_changes_only_the_given_fields() {
+    let repository = repository();
+    let req = patch(
+        5,
+        "application/merge-patch+json",
+        json!({ "name": "nashi" }),
+    );
+
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"2\"");
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body, json!({ "id": 5, "name": "nashi", "version": 2 }));
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "nashi");
+}
+
+#[actix_web::test]
+async fn json_patch_is_applied_only_if_its_tests_pass() {
+    let repository = repository();
+    let rename = |from: &str| {
+        patch(
+            5,
+            "application/json-patch+json",
+            json!([
+                { "op": "test", "path": "/name", "value": from },
+                { "op": "replace", "path": "/name", "value": "nashi" }
+            ]),
+        )
+    };
+
+    let response = call(&repository, rename("apple")).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "pear");
+
+    let response = call(&repository, rename("pear")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "nashi");
+}
+
+#[actix_web::test]
+async fn patched_fruit_is_validated() {
+    let repository = repository();
+
+    let blank = patch(5, "application/merge-patch+json", json!({ "name": " " }));
+    let response = call(&repository, blank).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "name");
+
+    let moved = patch(5, "application/merge-patch+json", json!({ "id": 6 }));
+    assert_eq!(
+        call(&repository, moved).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    let nameless = patch(5, "application/merge-patch+json", json!({ "name": null }));
+    assert_eq!(
+        call(&repository, nameless).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+
+    let fruits = all(&repository);
+    assert_eq!(fruits.len(), 1);
+    assert_eq!((fruits[0].name.as_str(), fruits[0].version), ("pear", 1));
+}
+
+#[actix_web::test]
+async fn patch_needs_a_patch_media_type_and_an_existing_fruit() {
+    let repository = repository();
+
+    let response = call(
+        &repository,
+        patch(5, "application/json", json!({ "name": "nashi" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(
+        response.headers().get("accept-patch").unwrap(),
+        "application/merge-patch+json, application/json-patch+json"
+    );
+
+    let req = patch(
+        6,
+        "application/merge-patch+json",
+        json!({ "name": "nashi" }),
+    );
+    assert_eq!(call(&repository, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn patch_honours_if_match() {
+    let repository = repository();
+    let req = patch(
+        5,
+        "application/merge-patch+json",
+        json!({ "name": "nashi" }),
+    )
+    .insert_header((header::IF_MATCH, "\"2\""));
+
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "pear");
+}
+
+// A store in which somebody else renames a fruit whenever one is about to be put.
+struct Contended(Arc<dyn FruitRepository>);
+
+impl FruitRepository for Contended {
+    fn get(&self, id: u32) -> Result<Option<Fruit>, RepositoryError> {
+        self.0.get(id)
+    }
+
+    fn list(&self, query: &FruitQuery) -> Result<(Vec<Fruit>, usize), RepositoryError> {
+        self.0.list(query)
+    }
+
+    fn create(&self, name: String) -> Result<Fruit, RepositoryError> {
+        self.0.create(name)
+    }
+
+    fn put(
+        &self,
+        fruit: Fruit,
+        precondition: &Precondition,
+    ) -> Result<(Saved, Fruit), RepositoryError> {
+        let rival = Fruit {
+            name: "rival".to_string(),
+            ..fruit.clone()
+        };
+        self.0.put(rival, &Precondition::Unconditional)?;
+        self.0.put(fruit, precondition)
+    }
+
+    fn delete(
+        &self,
+        id: u32,
+        precondition: &Precondition,
+    ) -> Result<Option<Fruit>, RepositoryError> {
+        self.0.delete(id, precondition)
+    }
+
+    fn batch(&self, writes: Vec<Write>) -> Result<Vec<Written>, BatchError> {
+        self.0.batch(writes)
+    }
+
+    fn pending_events(&self, limit: usize) -> Result<Vec<FruitEvent>, RepositoryError> {
+        self.0.pending_events(limit)
+    }
+
+    fn remove_events(&self, sequence: u64) -> Result<(), RepositoryError> {
+        self.0.remove_events(sequence)
+    }
+}
+
+#[actix_web::test]
+async fn patch_gives_up_on_a_fruit_that_keeps_changing() {
+    let repository: Arc<dyn FruitRepository> = Arc::new(Contended(repository()));
+    let req = patch(
+        5,
+        "application/merge-patch+json",
+        json!({ "name": "nashi" }),
+    );
+
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "rival");
+}
+
+fn batch(operations: Value) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/api/v2/fruits:batch")
+        .set_json(json!({ "operations": operations }))
+}
+
+fn statuses(results: &Value) -> Vec<u64> {
+    results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_u64().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn batch_makes_every_operation_and_reports_each() {
+    let repository = repository();
+    let operations = json!([
+        { "op": "create", "name": "plum" },
+        { "op": "put", "id": 5, "name": "nashi", "if_version": 1 },
+        { "op": "put", "id": 9, "name": "fig" },
+        { "op": "delete", "id": 6 }
+    ]);
+
+    let response = call(&repository, batch(operations)).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(statuses(&body["results"]), vec![201, 200, 201, 204]);
+    assert_eq!(
+        body["results"][1]["fruit"],
+        json!({ "id": 5, "name": "nashi", "version": 2 })
+    );
+    let names: Vec<String> = all(&repository)
+        .into_iter()
+        .map(|fruit| fruit.name)
+        .collect();
+    assert_eq!(names, vec!["nashi", "fig"]);
+}
+
+#[actix_web::test]
+async fn batch_with_a_failed_operation_changes_nothing() {
+    let repository = repository();
+    let operations = json!([
+        { "op": "create", "name": "plum" },
+        { "op": "put", "id": 5, "name": "nashi" },
+        { "op": "delete", "id": 7 }
+    ]);
+
+    let response = call(&repository, batch(operations)).await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/problem+json"
+    );
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(statuses(&body["results"]), vec![424, 424, 404]);
+    assert_eq!(
+        body["results"][2]["problem"]["detail"],
+        "There is no fruit with id 7"
+    );
+    let fruits = all(&repository);
+    assert_eq!(fruits.len(), 1);
+    assert_eq!((fruits[0].name.as_str(), fruits[0].version), ("pear", 1));
+    // The id taken by the create that was rolled back is free again.
+    let response = call(&repository, create(json!({ "name": "plum" }))).await;
+    let fruit: Fruit = test::read_body_json(response).await;
+    assert_eq!(fruit.id, 6);
+}
+
+#[actix_web::test]
+async fn batch_reports_every_invalid_operation_at_once() {
+    let repository = repository();
+    let operations = json!([
+        { "op": "create", "name": " " },
+        { "op": "delete", "id": 5 },
+        { "op": "put", "id": 5, "name": "" }
+    ]);
+
+    let response = call(&repository, batch(operations)).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(statuses(&body["results"]), vec![400, 424, 400]);
+    assert_eq!(body["results"][0]["problem"]["field"], "name");
+    assert_eq!(all(&repository).len(), 1);
+}
+
+#[actix_web::test]
+async fn batch_honours_if_version() {
+    let repository = repository();
+    let operations = json!([{ "op": "delete", "id": 5, "if_version": 2 }]);
+
+    let response = call(&repository, batch(operations)).await;
+
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(all(&repository).len(), 1);
+}
+
+#[actix_web::test]
+async fn batch_of_unknown_operations_is_rejected() {
+    let repository = repository();
+    let operations = json!([{ "op": "rename", "id": 5, "name": "nashi" }]);
+
+    let response = call(&repository, batch(operations)).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(repository.get(5).unwrap().unwrap().name, "pear");
+}
+
+fn catalogue() -> Arc<dyn FruitRepository> {
+    let names = ["pear", "apple", "plum", "Peach", "banana", "apricot"];
+    repository_of(
+        names
+            .iter()
+            .zip(1..)
+            .map(|(name, id)| Fruit {
+                id,
+                name: name.to_string(),
+                version: 1,
+            })
+            .collect(),
+    )
+}
+
+async fn list(repository: &Arc<dyn FruitRepository>, query: &str) -> Value {
+    let req = test::TestRequest::get().uri(&format!("/api/v2/fruits{query}"));
+    let response = call(repository, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    test::read_body_json(response).await
+}
+
+fn ids(page: &Value) -> Vec<u64> {
+    page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|fruit| fruit["id"].as_u64().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn list_is_one_page_sorted_by_id_by_default() {
+    let page = list(&catalogue(), "").await;
+
+    assert_eq!(ids(&page), [1, 2, 3, 4, 5, 6]);
+    assert_eq!(page["total"], 6);
+    assert_eq!(page["offset"], 0);
+    assert_eq!(page["limit"], 20);
+    assert_eq!(page["next"], Value::Null);
+}
+
+#[actix_web::test]
+async fn pages_link_to_the_next_page_until_the_last() {
+    let repository = catalogue();
+
+    let first = list(&repository, "?limit=4&sort=-id").await;
+    assert_eq!(ids(&first), [6, 5, 4, 3]);
+    assert_eq!(first["total"], 6);
+    assert_eq!(
+        first["next"],
+        "http://localhost:8080/api/v2/fruits?limit=4&offset=4&sort=-id"
+    );
+
+    let next = first["next"].as_str().unwrap();
+    let second = list(&repository, &next[next.find('?').unwrap()..]).await;
+    assert_eq!(ids(&second), [2, 1]);
+    assert_eq!(second["next"], Value::Null);
+}
+
+#[actix_web::test]
+async fn list_is_filtered_by_name_prefix_and_sorted_by_name() {
+    let page = list(&catalogue(), "?name=P&sort=name&limit=2").await;
+
+    let names: Vec<&str> = page["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|fruit| fruit["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["Peach", "pear"]);
+    assert_eq!(page["total"], 3);
+    assert_eq!(
+        page["next"],
+        "http://localhost:8080/api/v2/fruits?limit=2&offset=2&sort=name&name=P"
+    );
+}
+
+#[actix_web::test]
+async fn limit_out_of_range_is_a_problem() {
+    let req = test::TestRequest::get().uri("/api/v2/fruits?limit=0");
+
+    let response = call(&catalogue(), req).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "limit");
+}
+
+#[actix_web::test]
+async fn offset_out_of_range_is_a_problem() {
+    // SQLite cannot skip more than `i64::MAX` rows.
+    let repository: Arc<dyn FruitRepository> =
+        Arc::new(SqliteFruitRepository::in_memory(vec![pear()]).unwrap());
+    let req = test::TestRequest::get().uri("/api/v2/fruits?offset=18446744073709551615");
+
+    let response = call(&repository, req).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["field"], "offset");
+}
+
+#[actix_web::test]
+async fn list_has_an_etag_that_changes_with_its_fruits() {
+    let repository = catalogue();
+    let req = || test::TestRequest::get().uri("/api/v2/fruits?limit=2");
+
+    let response = call(&repository, req()).await;
+    let etag = response.headers().get(header::ETAG).unwrap().clone();
+    let response = call(
+        &repository,
+        req().insert_header((header::IF_NONE_MATCH, etag.clone())),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    call(&repository, put(2, json!({ "id": 2, "name": "quince" }))).await;
+    let response = call(
+        &repository,
+        req().insert_header((header::IF_NONE_MATCH, etag.clone())),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers().get(header::ETAG).unwrap(), etag);
+}
@@ -0,0 +1,480 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::test;
+use futures::future::join;
+use gateway::{
+    repository::{FruitRepository, Precondition},
+    rest::Fruit,
+};
+use serde_json::{json, Value};
+
+use common::{
+    call, graphql, in_memory_transport, repository_of, start, start_gateway,
+    start_gateway_with_fruits, start_user_service, Gateway, RecordingTransport, REPLY_TIMEOUT,
+};
+
+#[actix_web::test]
+async fn value_and_animal_type_do_not_need_the_user_service() {
+    let gateway = start_gateway(in_memory_transport(), REPLY_TIMEOUT).await;
+
+    let response = graphql(gateway, "{ value, getAnimalType }").await;
+
+    assert_eq!(
+        response,
+        json!({ "data": { "value": 0, "getAnimalType": "" } })
+    );
+}
+
+#[actix_web::test]
+async fn person_is_answered_by_the_user_service() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway,
+        "{ person(number: 2) { name, id { number, department } } }",
+    )
+    .await;
+
+    assert_eq!(
+        response,
+        json!({ "data": { "person": {
+            "name": "Bob",
+            "id": { "number": 2, "department": "Finance" }
+        } } })
+    );
+}
+
+#[actix_web::test]
+async fn unknown_person_is_null_without_an_error() {
+    let gateway = start().await;
+
+    let response = graphql(gateway, "{ person(number: 99) { name } }").await;
+
+    assert_eq!(response, json!({ "data": { "person": null } }));
+}
+
+#[actix_web::test]
+async fn persons_are_answered_by_the_user_service() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway,
+        "{ persons { nodes { name, id { department } }, pageInfo { hasNextPage } } }",
+    )
+    .await;
+
+    assert_eq!(
+        response,
+        json!({ "data": { "persons": {
+            "nodes": [
+                { "name": "Alice", "id": { "department": "Executive" } },
+                { "name": "Bob", "id": { "department": "Finance" } },
+                { "name": "Charlie", "id": { "department": "Operations" } }
+            ],
+            "pageInfo": { "hasNextPage": false }
+        } } })
+    );
+}
+
+// The numbers of the persons on the page, whether there are pages either side of it, and the
+// cursors of its ends.
+async fn person_page(gateway: Gateway, arguments: &str) -> (Value, Value, Value, Value) {
+    let response = graphql(
+        gateway,
+        &format!(
+            "{{ persons({arguments}) {{
+                nodes {{ id {{ number }} }}
+                pageInfo {{ hasPreviousPage, hasNextPage, startCursor, endCursor }}
+            }} }}"
+        ),
+    )
+    .await;
+    assert_eq!(response["errors"], json!(null), "{arguments}");
+    let persons = &response["data"]["persons"];
+    let numbers = persons["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["id"]["number"].clone())
+        .collect();
+    let page_info = &persons["pageInfo"];
+    (
+        Value::Array(numbers),
+        json!([page_info["hasPreviousPage"], page_info["hasNextPage"]]),
+        page_info["startCursor"].clone(),
+        page_info["endCursor"].clone(),
+    )
+}
+
+#[actix_web::test]
+async fn persons_are_paged_forward_and_backward_with_cursors() {
+    let gateway = start().await;
+
+    let (numbers, more, _, end) = person_page(gateway.clone(), "first: 2").await;
+    assert_eq!((numbers, more), (json!([1, 2]), json!([false, true])));
+
+    let after = format!("first: 2, after: {end}");
+    let (numbers, more, start, _) = person_page(gateway.clone(), &after).await;
+    assert_eq!((numbers, more), (json!([3]), json!([true, false])));
+
+    let before = format!("last: 2, before: {start}");
+    let (numbers, more, _, _) = person_page(gateway.clone(), &before).await;
+    assert_eq!((numbers, more), (json!([1, 2]), json!([false, true])));
+
+    let (numbers, more, _, _) = person_page(gateway, "last: 1").await;
+    assert_eq!((numbers, more), (json!([3]), json!([true, false])));
+}
+
+#[actix_web::test]
+async fn persons_refuse_an_oversized_page_or_a_bad_cursor() {
+    let gateway = start().await;
+
+    let response = graphql(gateway.clone(), "{ persons(last: 101) { nodes { name } } }").await;
+    assert_eq!(response["data"]["persons"], json!(null));
+    assert_eq!(
+        response["errors"][0]["extensions"]["code"],
+        "VALIDATION_ERROR"
+    );
+    assert_eq!(response["errors"][0]["extensions"]["field"], "last");
+
+    let response = graphql(gateway, r#"{ persons(after: "3") { nodes { name } } }"#).await;
+    assert_eq!(response["data"]["persons"], json!(null));
+}
+
+#[actix_web::test]
+async fn persons_refuse_first_with_last() {
+    let gateway = start().await;
+
+    let response = graphql(gateway, "{ persons(first: 2, last: 1) { nodes { name } } }").await;
+
+    assert_eq!(response["data"]["persons"], json!(null));
+    assert_eq!(
+        response["errors"][0]["extensions"]["code"],
+        "VALIDATION_ERROR"
+    );
+    assert_eq!(response["errors"][0]["extensions"]["field"], "last");
+}
+
+#[actix_web::test]
+async fn persons_are_filtered_by_department_and_name() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway,
+        r#"{
+            finance: persons(department: "finance") { nodes { name } }
+            withLi: persons(nameContains: "LI") { nodes { name } }
+            none: persons(department: "Finance", nameContains: "li") { nodes { name } }
+        }"#,
+    )
+    .await;
+
+    assert_eq!(
+        response,
+        json!({ "data": {
+            "finance": { "nodes": [{ "name": "Bob" }] },
+            "withLi": { "nodes": [{ "name": "Alice" }, { "name": "Charlie" }] },
+            "none": { "nodes": [] }
+        } })
+    );
+}
+
+#[actix_web::test]
+async fn all_fields_in_one_query() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway,
+        "{ value, person(number: 1) { name }, persons { nodes { id { department } } }, getAnimalType }",
+    )
+    .await;
+
+    assert_eq!(response["errors"], json!(null));
+    assert_eq!(response["data"]["value"], json!(0));
+    assert_eq!(response["data"]["person"]["name"], json!("Alice"));
+    assert_eq!(
+        response["data"]["persons"]["nodes"]
+            .as_array()
+            .unwrap()
+            .len(),
+        3
+    );
+    assert_eq!(response["data"]["getAnimalType"], json!(""));
+}
+
+#[actix_web::test]
+async fn persons_by_number_in_one_query_are_one_request() {
+    let transport = in_memory_transport();
+    start_user_service(transport.clone()).await;
+    // Every request to the user-service is one message.
+    let recording = Arc::new(RecordingTransport::new(transport));
+    let gateway = start_gateway(recording.clone(), REPLY_TIMEOUT).await;
+
+    let response = graphql(
+        gateway,
+        "{
+            a: person(number: 3) { name }
+            b: person(number: 1) { name }
+            c: person(number: 9) { name }
+            d: person(number: 1) { id { department } }
+        }",
+    )
+    .await;
+
+    assert_eq!(
+        response,
+        json!({ "data": {
+            "a": { "name": "Charlie" },
+            "b": { "name": "Alice" },
+            "c": null,
+            "d": { "id": { "department": "Executive" } }
+        } })
+    );
+    assert_eq!(recording.published(), 1);
+}
+
+#[actix_web::test]
+async fn queries_running_at_once_do_not_share_person_lookups() {
+    let transport = in_memory_transport();
+    start_user_service(transport.clone()).await;
+    // Every request to the user-service is one message.
+    let recording = Arc::new(RecordingTransport::new(transport));
+    let gateway = start_gateway(recording.clone(), REPLY_TIMEOUT).await;
+
+    let (alice, charlie) = join(
+        graphql(gateway.clone(), "{ person(number: 1) { name } }"),
+        graphql(gateway, "{ person(number: 3) { name } }"),
+    )
+    .await;
+
+    assert_eq!(alice, json!({ "data": { "person": { "name": "Alice" } } }));
+    assert_eq!(
+        charlie,
+        json!({ "data": { "person": { "name": "Charlie" } } })
+    );
+    assert_eq!(recording.published(), 2);
+}
+
+#[actix_web::test]
+async fn person_times_out_when_the_user_service_does_not_reply() {
+    let gateway = start_gateway(in_memory_transport(), Duration::from_millis(50)).await;
+
+    let response = graphql(gateway, "{ value, person(number: 1) { name } }").await;
+
+    assert_eq!(response["data"], json!({ "value": 0, "person": null }));
+    let error = &response["errors"][0];
+    assert_eq!(error["path"], json!(["person"]));
+    assert_eq!(error["extensions"]["code"], json!("BACKEND_TIMEOUT"));
+    assert_eq!(error["extensions"]["retryable"], json!(true));
+    assert!(error["extensions"]["requestId"].is_string());
+}
+
+fn fruits() -> Arc<dyn FruitRepository> {
+    let fruits = ["apple", "banana", "cherry", "blueberry"]
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| Fruit {
+            id: index as u32 + 1,
+            name: name.to_string(),
+            version: 1,
+        })
+        .collect();
+    repository_of(fruits)
+}
+
+async fn fruit_gateway(repository: &Arc<dyn FruitRepository>) -> Gateway {
+    start_gateway_with_fruits(in_memory_transport(), REPLY_TIMEOUT, repository.clone()).await
+}
+
+fn error_code(response: &Value) -> &Value {
+    &response["errors"][0]["extensions"]["code"]
+}
+
+#[actix_web::test]
+async fn fruit_by_id() {
+    let repository = fruits();
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        "{ fruit(id: 2) { id, name, version } }",
+    )
+    .await;
+
+    assert_eq!(
+        response,
+        json!({ "data": { "fruit": { "id": 2, "name": "banana", "version": 1 } } })
+    );
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        "{ fruit(id: 9) { name } }",
+    )
+    .await;
+    assert_eq!(response, json!({ "data": { "fruit": null } }));
+}
+
+#[actix_web::test]
+async fn fruits_are_paged_with_cursors() {
+    let repository = fruits();
+    let page = |after: &str| {
+        format!(
+            "{{ fruits(first: 2{after}) {{
+                totalCount
+                edges {{ cursor, node {{ name }} }}
+                pageInfo {{ hasPreviousPage, hasNextPage, endCursor }}
+            }} }}"
+        )
+    };
+
+    let first = graphql(fruit_gateway(&repository).await, &page("")).await;
+    let fruits = &first["data"]["fruits"];
+    assert_eq!(fruits["totalCount"], 4);
+    assert_eq!(fruits["edges"][0]["node"]["name"], "apple");
+    assert_eq!(fruits["edges"][1]["node"]["name"], "banana");
+    assert_eq!(fruits["pageInfo"]["hasPreviousPage"], false);
+    assert_eq!(fruits["pageInfo"]["hasNextPage"], true);
+    let end_cursor = fruits["pageInfo"]["endCursor"].as_str().unwrap();
+    assert_eq!(end_cursor, fruits["edges"][1]["cursor"]);
+
+    // The next page starts after the last fruit seen, whatever became of the fruits before it.
+    repository.delete(1, &Precondition::Unconditional).unwrap();
+    let second = graphql(
+        fruit_gateway(&repository).await,
+        &page(&format!(", after: \"{end_cursor}\"")),
+    )
+    .await;
+    let fruits = &second["data"]["fruits"];
+    let names: Vec<_> = fruits["edges"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|edge| edge["node"]["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["cherry", "blueberry"]);
+    assert_eq!(fruits["pageInfo"]["hasPreviousPage"], true);
+    assert_eq!(fruits["pageInfo"]["hasNextPage"], false);
+}
+
+#[actix_web::test]
+async fn fruits_are_filtered_by_name_prefix() {
+    let response = graphql(
+        fruit_gateway(&fruits()).await,
+        r#"{ fruits(filter: { namePrefix: "B" }) { totalCount, edges { node { name } } } }"#,
+    )
+    .await;
+
+    assert_eq!(
+        response,
+        json!({ "data": { "fruits": {
+            "totalCount": 2,
+            "edges": [{ "node": { "name": "banana" } }, { "node": { "name": "blueberry" } }]
+        } } })
+    );
+}
+
+#[actix_web::test]
+async fn fruits_refuse_a_bad_cursor_or_page_size() {
+    let repository = fruits();
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        r#"{ fruits(after: "nonsense") { totalCount } }"#,
+    )
+    .await;
+    assert_eq!(response["data"]["fruits"], Value::Null);
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("not a cursor"));
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        "{ fruits(first: 101) { totalCount } }",
+    )
+    .await;
+    assert_eq!(response["data"]["fruits"], Value::Null);
+    assert_eq!(error_code(&response), "VALIDATION_ERROR");
+    assert_eq!(response["errors"][0]["extensions"]["field"], "first");
+}
+
+#[actix_web::test]
+async fn mutations_change_the_store_the_rest_api_reads() {
+    let repository = fruits();
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        r#"mutation { createFruit(name: " fig ") { id, name, version } }"#,
+    )
+    .await;
+    assert_eq!(
+        response,
+        json!({ "data": { "createFruit": { "id": 5, "name": "fig", "version": 1 } } })
+    );
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        r#"mutation { updateFruit(id: 5, name: "black fig", ifVersion: 1) { name, version } }"#,
+    )
+    .await;
+    assert_eq!(
+        response,
+        json!({ "data": { "updateFruit": { "name": "black fig", "version": 2 } } })
+    );
+
+    let response = call(
+        &repository,
+        test::TestRequest::get().uri("/api/v2/fruits/5"),
+    )
+    .await;
+    let fruit: Value = test::read_body_json(response).await;
+    assert_eq!(fruit, json!({ "id": 5, "name": "black fig", "version": 2 }));
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        "mutation { deleteFruit(id: 5) { name } }",
+    )
+    .await;
+    assert_eq!(
+        response,
+        json!({ "data": { "deleteFruit": { "name": "black fig" } } })
+    );
+    assert!(repository.get(5).unwrap().is_none());
+}
+
+#[actix_web::test]
+async fn refused_mutations_are_errors_with_a_code() {
+    let repository = fruits();
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        r#"mutation { createFruit(name: " ") { id } }"#,
+    )
+    .await;
+    assert_eq!(response["data"]["createFruit"], Value::Null);
+    assert_eq!(error_code(&response), "VALIDATION_ERROR");
+    assert_eq!(response["errors"][0]["extensions"]["field"], "name");
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        r#"mutation { updateFruit(id: 9, name: "fig") { id } }"#,
+    )
+    .await;
+    assert_eq!(error_code(&response), "NOT_FOUND");
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        r#"mutation { updateFruit(id: 1, name: "fig", ifVersion: 7) { id } }"#,
+    )
+    .await;
+    assert_eq!(error_code(&response), "PRECONDITION_FAILED");
+    assert_eq!(repository.get(1).unwrap().unwrap().name, "apple");
+
+    let response = graphql(
+        fruit_gateway(&repository).await,
+        "mutation { deleteFruit(id: 9) { id } }",
+    )
+    .await;
+    assert_eq!(error_code(&response), "NOT_FOUND");
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use serde_json::json;
+
+use common::{graphql, start};
+
+#[actix_web::test]
+async fn created_person_gets_the_next_number_and_can_be_queried() {
+    let gateway = start().await;
+
+    let created = graphql(
+        gateway.clone(),
+        r#"mutation { createPerson(name: " Dana ", department: "Finance") {
+            name, id { number, department }
+        } }"#,
+    )
+    .await;
+    let queried = graphql(
+        gateway,
+        r#"{ persons(department: "Finance") { nodes { name, id { number } } } }"#,
+    )
+    .await;
+
+    assert_eq!(
+        created,
+        json!({ "data": { "createPerson": {
+            "name": "Dana",
+            "id": { "number": 4, "department": "Finance" }
+        } } })
+    );
+    assert_eq!(
+        queried,
+        json!({ "data": { "persons": { "nodes": [
+            { "name": "Bob", "id": { "number": 2 } },
+            { "name": "Dana", "id": { "number": 4 } }
+        ] } } })
+    );
+}
+
+#[actix_web::test]
+async fn update_changes_only_the_given_fields() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway,
+        r#"mutation { updatePerson(number: 2, department: "Operations") {
+            name, id { number, department }
+        } }"#,
+    )
+    .await;
+
+    assert_eq!(
+        response,
+        json!({ "data": { "updatePerson": {
+            "name": "Bob",
+            "id": { "number": 2, "department": "Operations" }
+        } } })
+    );
+}
+
+#[actix_web::test]
+async fn deleted_person_is_returned_and_gone_afterwards() {
+    let gateway = start().await;
+
+    let deleted = graphql(
+        gateway.clone(),
+        "mutation { deletePerson(number: 3) { name } }",
+    )
+    .await;
+    let queried = graphql(gateway, "{ person(number: 3) { name } }").await;
+
+    assert_eq!(
+        deleted,
+        json!({ "data": { "deletePerson": { "name": "Charlie" } } })
+    );
+    assert_eq!(queried, json!({ "data": { "person": null } }));
+}
+
+#[actix_web::test]
+async fn deleted_numbers_are_not_reused() {
+    let gateway = start().await;
+
+    graphql(
+        gateway.clone(),
+        "mutation { deletePerson(number: 3) { name } }",
+    )
+    .await;
+    let created = graphql(
+        gateway,
+        r#"mutation { createPerson(name: "Dana", department: "Finance") { id { number } } }"#,
+    )
+    .await;
+
+    assert_eq!(
+        created,
+        json!({ "data": { "createPerson": { "id": { "number": 4 } } } })
+    );
+}
+
+#[actix_web::test]
+async fn blank_name_is_a_validation_error_on_that_field() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway.clone(),
+        r#"mutation { createPerson(name: "  ", department: "Finance") { name } }"#,
+    )
+    .await;
+    let queried = graphql(gateway, "{ persons { nodes { name } } }").await;
+
+    assert_eq!(response["data"], json!({ "createPerson": null }));
+    let error = &response["errors"][0];
+    assert_eq!(error["path"], json!(["createPerson"]));
+    assert_eq!(error["extensions"]["code"], "VALIDATION_ERROR");
+    assert_eq!(error["extensions"]["field"], "name");
+    assert_eq!(error["extensions"]["retryable"], false);
+    assert_eq!(
+        queried["data"]["persons"]["nodes"]
+            .as_array()
+            .unwrap()
+            .len(),
+        3
+    );
+}
+
+#[actix_web::test]
+async fn rejected_update_changes_nothing() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway.clone(),
+        r#"mutation { updatePerson(number: 2, name: "Robert", department: "") { name } }"#,
+    )
+    .await;
+    let queried = graphql(gateway, "{ person(number: 2) { name } }").await;
+
+    assert_eq!(response["errors"][0]["extensions"]["field"], "department");
+    assert_eq!(queried, json!({ "data": { "person": { "name": "Bob" } } }));
+}
+
+#[actix_web::test]
+async fn changing_an_unknown_person_is_not_found() {
+    let gateway = start().await;
+
+    let response = graphql(
+        gateway,
+        r#"mutation {
+            updatePerson(number: 99, name: "Zed") { name }
+            deletePerson(number: 99) { name }
+        }"#,
+    )
+    .await;
+
+    assert_eq!(
+        response["data"],
+        json!({ "updatePerson": null, "deletePerson": null })
+    );
+    let errors = response["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    for error in errors {
+        assert_eq!(error["extensions"]["code"], "NOT_FOUND");
+        assert_eq!(error["extensions"]["retryable"], false);
+    }
+}
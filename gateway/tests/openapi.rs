@@ -0,0 +1,107 @@
+use actix_web::{
+    http::{header, StatusCode},
+    test, App,
+};
+use gateway::routes::configure;
+use serde_json::Value;
+
+async fn get(uri: &str) -> actix_web::dev::ServiceResponse {
+    let app = test::init_service(App::new().configure(configure)).await;
+    test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await
+}
+
+async fn document() -> Value {
+    let response = get("/api/openapi.json").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    test::read_body_json(response).await
+}
+
+#[actix_web::test]
+async fn document_describes_every_api_scope() {
+    let document = document().await;
+
+    assert!(document["openapi"].as_str().unwrap().starts_with("3."));
+    let paths = document["paths"].as_object().unwrap();
+    for path in [
+        "/api/api-get",
+        "/api/v1/api-v1-get",
+        "/api/v2/api-v2-get-b-query-params",
+        "/api/v2/struct-path-query/{age}/{animal}",
+        "/api/v2/fruits",
+        "/api/v2/fruits:batch",
+        "/api/v2/fruits/{id}",
+    ] {
+        assert!(paths.contains_key(path), "{path} is not described");
+    }
+    let fruit = &paths["/api/v2/fruits/{id}"];
+    for method in ["get", "put", "patch", "delete"] {
+        assert!(fruit.get(method).is_some(), "{method} is not described");
+    }
+}
+
+#[actix_web::test]
+async fn document_describes_models_and_parameters() {
+    let document = document().await;
+
+    let schemas = &document["components"]["schemas"];
+    for schema in ["Fruit", "Animal", "Input", "Problem", "FruitOperation"] {
+        assert!(schemas.get(schema).is_some(), "{schema} is not described");
+    }
+    assert!(schemas["Fruit"]["properties"].get("version").is_some());
+
+    let parameters: Vec<&str> = document["paths"]["/api/v2/api-v2-get-b-query-params"]["get"]
+        ["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|parameter| parameter["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(parameters, vec!["required_input", "maybe_other_input"]);
+}
+
+#[actix_web::test]
+async fn patch_takes_both_patch_formats() {
+    let document = document().await;
+
+    let content = document["paths"]["/api/v2/fruits/{id}"]["patch"]["requestBody"]["content"]
+        .as_object()
+        .unwrap();
+    assert!(content.contains_key("application/merge-patch+json"));
+    assert!(content.contains_key("application/json-patch+json"));
+}
+
+#[actix_web::test]
+async fn docs_page_explores_the_document() {
+    let response = get("/api/docs").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let body = test::read_body(response).await;
+    assert!(std::str::from_utf8(&body)
+        .unwrap()
+        .contains("/api/openapi.json"));
+}
+
+#[actix_web::test]
+async fn fruits_are_described_in_every_format() {
+    let document = document().await;
+
+    let fruit = &document["paths"]["/api/v2/fruits/{id}"];
+    let put_body = fruit["put"]["requestBody"]["content"].as_object().unwrap();
+    let get_responses = &fruit["get"]["responses"];
+    let get_body = get_responses["200"]["content"].as_object().unwrap();
+    for media_type in [
+        "application/json",
+        "text/csv",
+        "application/msgpack",
+        "application/xml",
+    ] {
+        assert!(put_body.contains_key(media_type), "{media_type} body");
+        assert!(get_body.contains_key(media_type), "{media_type} response");
+    }
+    assert!(get_responses.get("406").is_some());
+    assert!(fruit["delete"]["responses"].get("406").is_none());
+}
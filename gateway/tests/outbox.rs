@@ -0,0 +1,133 @@
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{http::StatusCode, test};
+use gateway::outbox::OutboxRelay;
+use protocol::events::{FruitEvent, FruitEventKind};
+use serde_json::json;
+use transport::{memory::InMemoryTransport, StartFrom, Subscription, Transport};
+
+use common::{call, in_memory_transport, repository, RecordingTransport};
+
+const TOPIC: &str = "fruit_events";
+
+// Reads the next event off the topic, with its key.
+async fn next_event(subscription: &mut Box<dyn Subscription>) -> (String, FruitEvent) {
+    let envelope = subscription.next().await.unwrap();
+    (
+        envelope.key.unwrap(),
+        serde_json::from_str(&envelope.payload).unwrap(),
+    )
+}
+
+#[actix_web::test]
+async fn rest_changes_are_published_with_before_and_after() {
+    let repository = repository();
+    let transport = InMemoryTransport::new();
+    let mut subscription = transport
+        .subscribe("analytics", &[TOPIC], StartFrom::Earliest)
+        .await
+        .unwrap();
+    let relay = OutboxRelay::new(repository.clone(), Arc::new(transport), TOPIC.to_string());
+
+    let create = test::TestRequest::post()
+        .uri("/api/v2/fruits")
+        .set_json(json!({ "name": "plum" }));
+    assert_eq!(
+        call(&repository, create).await.status(),
+        StatusCode::CREATED
+    );
+    let update = test::TestRequest::put()
+        .uri("/api/v2/fruits/5")
+        .set_json(json!({ "id": 5, "name": "nashi" }));
+    assert_eq!(call(&repository, update).await.status(), StatusCode::OK);
+    let delete = test::TestRequest::delete().uri("/api/v2/fruits/6");
+    assert_eq!(
+        call(&repository, delete).await.status(),
+        StatusCode::NO_CONTENT
+    );
+    // A request that changes nothing publishes nothing.
+    let missing = test::TestRequest::delete().uri("/api/v2/fruits/6");
+    assert_eq!(
+        call(&repository, missing).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    assert_eq!(relay.publish_pending().await, 3);
+
+    let (key, created) = next_event(&mut subscription).await;
+    assert_eq!(key, "6");
+    assert_eq!(created.kind, FruitEventKind::FruitCreated);
+    assert!(created.before.is_none());
+    assert_eq!(created.after.unwrap().name, "plum");
+
+    let (key, updated) = next_event(&mut subscription).await;
+    assert_eq!(key, "5");
+    assert_eq!(updated.kind, FruitEventKind::FruitUpdated);
+    assert_eq!(updated.before.unwrap().name, "pear");
+    let after = updated.after.unwrap();
+    assert_eq!((after.name.as_str(), after.version), ("nashi", 2));
+
+    let (key, deleted) = next_event(&mut subscription).await;
+    assert_eq!(key, "6");
+    assert_eq!(deleted.kind, FruitEventKind::FruitDeleted);
+    assert_eq!(deleted.before.unwrap().name, "plum");
+    assert!(deleted.after.is_none());
+
+    assert!(repository.pending_events(10).unwrap().is_empty());
+    assert_eq!(relay.publish_pending().await, 0);
+}
+
+#[actix_web::test]
+async fn event_payload_is_flat_json() {
+    let repository = repository();
+    let transport = InMemoryTransport::new();
+    let mut subscription = transport
+        .subscribe("analytics", &[TOPIC], StartFrom::Earliest)
+        .await
+        .unwrap();
+    let relay = OutboxRelay::new(repository.clone(), Arc::new(transport), TOPIC.to_string());
+    repository.create("plum".to_string()).unwrap();
+
+    relay.publish_pending().await;
+
+    let payload: serde_json::Value =
+        serde_json::from_str(&subscription.next().await.unwrap().payload).unwrap();
+    assert_eq!(
+        payload,
+        json!({
+            "sequence": 1,
+            "type": "FruitCreated",
+            "before": null,
+            "after": { "id": 6, "name": "plum", "version": 1 },
+        })
+    );
+}
+
+#[actix_web::test]
+async fn events_wait_in_the_outbox_while_the_broker_is_down() {
+    let repository = repository();
+    let create = test::TestRequest::post()
+        .uri("/api/v2/fruits")
+        .set_json(json!({ "name": "plum" }));
+    assert_eq!(
+        call(&repository, create).await.status(),
+        StatusCode::CREATED
+    );
+
+    let unavailable = RecordingTransport::new(in_memory_transport()).failing_after(0);
+    let down = OutboxRelay::new(repository.clone(), Arc::new(unavailable), TOPIC.to_string());
+    assert_eq!(down.publish_pending().await, 0);
+    assert_eq!(repository.pending_events(10).unwrap().len(), 1);
+
+    let transport = InMemoryTransport::new();
+    let mut subscription = transport
+        .subscribe("analytics", &[TOPIC], StartFrom::Earliest)
+        .await
+        .unwrap();
+    let up = OutboxRelay::new(repository.clone(), Arc::new(transport), TOPIC.to_string());
+    assert_eq!(up.publish_pending().await, 1);
+    let (_, created) = next_event(&mut subscription).await;
+    assert_eq!(created.sequence, 1);
+}
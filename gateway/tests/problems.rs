@@ -0,0 +1,116 @@
+mod common;
+
+use actix_web::{
+    dev::ServiceResponse,
+    http::{header, StatusCode},
+    test,
+};
+use serde_json::Value;
+
+use common::{call, repository};
+
+// Checks the response is a problem with `status`, and returns it.
+async fn problem(response: ServiceResponse, status: StatusCode) -> Value {
+    assert_eq!(response.status(), status);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/problem+json"
+    );
+    let request_id = response
+        .headers()
+        .get("x-request-id")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], status.canonical_reason().unwrap());
+    assert_eq!(body["status"], status.as_u16());
+    assert_eq!(body["request_id"], request_id);
+    body
+}
+
+#[actix_web::test]
+async fn missing_query_parameter_is_a_problem() {
+    let req = test::TestRequest::get().uri("/api/v2/api-v2-get-b-query-params");
+
+    let body = problem(call(&repository(), req).await, StatusCode::BAD_REQUEST).await;
+
+    assert_eq!(
+        body["detail"],
+        "Query deserialize error: missing field `required_input`"
+    );
+}
+
+#[actix_web::test]
+async fn path_that_does_not_parse_is_not_found() {
+    let req = test::TestRequest::get().uri("/api/v2/dynamic_segments/old/dog");
+
+    let body = problem(call(&repository(), req).await, StatusCode::NOT_FOUND).await;
+
+    assert!(body["detail"]
+        .as_str()
+        .unwrap()
+        .starts_with("Path deserialize error"));
+}
+
+#[actix_web::test]
+async fn malformed_json_body_is_a_problem() {
+    let req = test::TestRequest::post()
+        .uri("/post-with-body-deserialized")
+        .insert_header(header::ContentType::json())
+        .set_payload(r#"{ "maybe_other_input": "x" }"#);
+
+    let body = problem(call(&repository(), req).await, StatusCode::BAD_REQUEST).await;
+
+    assert!(body["detail"]
+        .as_str()
+        .unwrap()
+        .contains("missing field `required_input`"));
+}
+
+#[actix_web::test]
+async fn json_body_of_another_content_type_is_unsupported() {
+    let req = test::TestRequest::post()
+        .uri("/api/v2/fruits")
+        .insert_header(header::ContentType::plaintext())
+        .set_payload(r#"{ "name": "plum" }"#);
+
+    problem(
+        call(&repository(), req).await,
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+    )
+    .await;
+}
+
+#[actix_web::test]
+async fn missing_fruit_is_a_problem() {
+    let req = test::TestRequest::get().uri("/api/v2/fruits/99999");
+
+    let body = problem(call(&repository(), req).await, StatusCode::NOT_FOUND).await;
+
+    assert_eq!(body["detail"], "There is no fruit with id 99999");
+}
+
+#[actix_web::test]
+async fn request_id_from_the_client_is_kept() {
+    let req = test::TestRequest::get()
+        .uri("/api/v2/fruits/1")
+        .insert_header(("x-request-id", "import-2022-11-03.42"));
+
+    let body = problem(call(&repository(), req).await, StatusCode::NOT_FOUND).await;
+
+    assert_eq!(body["request_id"], "import-2022-11-03.42");
+}
+
+#[actix_web::test]
+async fn unusable_request_id_is_replaced() {
+    let req = test::TestRequest::get()
+        .uri("/api/v2/fruits/1")
+        .insert_header(("x-request-id", "<script>"));
+
+    let body = problem(call(&repository(), req).await, StatusCode::NOT_FOUND).await;
+
+    assert_ne!(body["request_id"], "<script>");
+}
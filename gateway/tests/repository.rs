@@ -0,0 +1,290 @@
+use gateway::{
+    repository::{
+        BatchError, FruitQuery, FruitRepository, InMemoryFruitRepository, JsonFileFruitRepository,
+        Precondition, RepositoryError, Saved, SqliteFruitRepository, Write, Written, MAX_FRUIT_ID,
+    },
+    rest::{Fruit, FruitSort},
+};
+use protocol::events::FruitEventKind::{FruitCreated, FruitDeleted, FruitUpdated};
+use tempfile::TempDir;
+
+fn fruit(id: u32, name: &str) -> Fruit {
+    Fruit {
+        id,
+        name: name.to_string(),
+        version: 1,
+    }
+}
+
+fn seed() -> Vec<Fruit> {
+    vec![fruit(5, "pear"), fruit(2, "Peach"), fruit(3, "apple")]
+}
+
+fn names(repository: &dyn FruitRepository, query: FruitQuery) -> (Vec<String>, usize) {
+    let (fruits, total) = repository.list(&query).unwrap();
+    (fruits.into_iter().map(|fruit| fruit.name).collect(), total)
+}
+
+// The behaviour every implementation shares, starting from `seed()`.
+fn check_contract(repository: &dyn FruitRepository) {
+    assert_eq!(repository.get(2).unwrap().unwrap().name, "Peach");
+    assert!(repository.get(4).unwrap().is_none());
+
+    let by_name = FruitQuery {
+        name_prefix: Some("p".to_string()),
+        sort: FruitSort::NameAscending,
+        offset: 0,
+        limit: 10,
+        ..FruitQuery::default()
+    };
+    assert_eq!(
+        names(repository, by_name),
+        (vec!["Peach".to_string(), "pear".to_string()], 2)
+    );
+    let second_page = FruitQuery {
+        sort: FruitSort::IdDescending,
+        offset: 1,
+        limit: 1,
+        ..FruitQuery::default()
+    };
+    assert_eq!(
+        names(repository, second_page),
+        (vec!["apple".to_string()], 3)
+    );
+    let after_peach = FruitQuery {
+        after_id: Some(2),
+        limit: 10,
+        ..FruitQuery::default()
+    };
+    assert_eq!(
+        names(repository, after_peach),
+        (vec!["apple".to_string(), "pear".to_string()], 3)
+    );
+
+    let anything = &Precondition::Unconditional;
+    let created = repository.create("plum".to_string()).unwrap();
+    assert_eq!((created.id, created.version), (6, 1));
+    let (saved, replaced) = repository.put(fruit(6, "damson"), anything).unwrap();
+    assert_eq!((saved, replaced.version), (Saved::Replaced, 2));
+    let (saved, added) = repository.put(fruit(9, "fig"), anything).unwrap();
+    assert_eq!((saved, added.version), (Saved::Created, 1));
+    assert_eq!(repository.get(6).unwrap().unwrap().name, "damson");
+
+    // A failed precondition changes nothing.
+    let stale = &Precondition::Versions(vec![1]);
+    assert!(matches!(
+        repository.put(fruit(6, "sloe"), stale),
+        Err(RepositoryError::PreconditionFailed { id: 6 })
+    ));
+    assert!(matches!(
+        repository.put(fruit(7, "sloe"), &Precondition::Exists),
+        Err(RepositoryError::PreconditionFailed { id: 7 })
+    ));
+    assert!(matches!(
+        repository.delete(6, stale),
+        Err(RepositoryError::PreconditionFailed { id: 6 })
+    ));
+    assert_eq!(repository.get(6).unwrap().unwrap().version, 2);
+    assert!(repository.get(7).unwrap().is_none());
+    let current = &Precondition::Versions(vec![2]);
+    assert_eq!(
+        repository.put(fruit(6, "sloe"), current).unwrap().1.version,
+        3
+    );
+
+    assert_eq!(
+        repository
+            .delete(9, &Precondition::Exists)
+            .unwrap()
+            .unwrap()
+            .name,
+        "fig"
+    );
+    assert!(repository.delete(9, anything).unwrap().is_none());
+    // Deleted ids are not handed out again.
+    assert_eq!(repository.create("kiwi".to_string()).unwrap().id, 10);
+
+    // A batch is made in order, and as a whole or not at all.
+    let failing = vec![
+        Write::Create {
+            name: "lime".to_string(),
+        },
+        Write::Delete {
+            id: 10,
+            precondition: Precondition::Exists,
+        },
+        Write::Delete {
+            id: 10,
+            precondition: Precondition::Exists,
+        },
+    ];
+    let failure = repository.batch(failing).unwrap_err();
+    assert_eq!(failure.index, Some(2));
+    assert!(matches!(
+        failure.error,
+        RepositoryError::PreconditionFailed { id: 10 }
+    ));
+    assert_eq!(repository.get(10).unwrap().unwrap().name, "kiwi");
+    assert!(repository.get(11).unwrap().is_none());
+
+    let written = repository
+        .batch(vec![
+            Write::Create {
+                name: "lime".to_string(),
+            },
+            Write::Put {
+                fruit: fruit(10, "gold kiwi"),
+                precondition: Precondition::Versions(vec![1]),
+            },
+        ])
+        .unwrap();
+    assert!(matches!(&written[0], Written::Created(lime) if lime.id == 11));
+    assert!(matches!(&written[1], Written::Put(Saved::Replaced, kiwi) if kiwi.version == 2));
+    assert_eq!(repository.get(10).unwrap().unwrap().name, "gold kiwi");
+
+    // Every change, and only those, left an event in the outbox.
+    let events = repository.pending_events(100).unwrap();
+    let changes: Vec<_> = events
+        .iter()
+        .map(|event| (event.sequence, event.kind, event.fruit_id()))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (1, FruitCreated, 6),
+            (2, FruitUpdated, 6),
+            (3, FruitCreated, 9),
+            (4, FruitUpdated, 6),
+            (5, FruitDeleted, 9),
+            (6, FruitCreated, 10),
+            (7, FruitCreated, 11),
+            (8, FruitUpdated, 10),
+        ]
+    );
+    let update = &events[1];
+    assert_eq!(update.before.as_ref().unwrap().name, "plum");
+    assert_eq!(update.after.as_ref().unwrap().name, "damson");
+    let deletion = &events[4];
+    assert_eq!(deletion.before.as_ref().unwrap().name, "fig");
+    assert!(deletion.after.is_none());
+
+    assert_eq!(repository.pending_events(2).unwrap().len(), 2);
+    repository.remove_events(5).unwrap();
+    assert_eq!(repository.pending_events(100).unwrap()[0].sequence, 6);
+    repository.remove_events(8).unwrap();
+    assert!(repository.pending_events(100).unwrap().is_empty());
+    // Sequence numbers are not reused once their events are removed.
+    repository.create("quince".to_string()).unwrap();
+    assert_eq!(repository.pending_events(100).unwrap()[0].sequence, 9);
+
+    // Ids stop at `MAX_FRUIT_ID` rather than wrapping around onto stored fruits.
+    let too_high = fruit(MAX_FRUIT_ID + 1, "date");
+    assert!(matches!(
+        repository.put(too_high, &Precondition::Unconditional),
+        Err(RepositoryError::IdOutOfRange { id }) if id == MAX_FRUIT_ID + 1
+    ));
+    repository
+        .put(fruit(MAX_FRUIT_ID, "date"), &Precondition::Unconditional)
+        .unwrap();
+    assert!(matches!(
+        repository.create("fig".to_string()),
+        Err(RepositoryError::IdsExhausted)
+    ));
+    let create = Write::Create {
+        name: "fig".to_string(),
+    };
+    assert!(matches!(
+        repository.batch(vec![create]),
+        Err(BatchError {
+            index: Some(0),
+            error: RepositoryError::IdsExhausted
+        })
+    ));
+    let query = FruitQuery {
+        limit: 10,
+        ..FruitQuery::default()
+    };
+    let (fruits, _) = repository.list(&query).unwrap();
+    assert_eq!(fruits.last().unwrap().id, MAX_FRUIT_ID);
+
+    // A fruit put where one was deleted carries on from its version, so a precondition written
+    // against the deleted fruit does not hold for the new one.
+    assert_eq!(repository.get(10).unwrap().unwrap().version, 2);
+    repository.delete(10, anything).unwrap();
+    let (saved, kiwi) = repository.put(fruit(10, "kiwi"), anything).unwrap();
+    assert_eq!((saved, kiwi.version), (Saved::Created, 3));
+    assert!(matches!(
+        repository.put(fruit(10, "hardy kiwi"), &Precondition::Versions(vec![1])),
+        Err(RepositoryError::PreconditionFailed { id: 10 })
+    ));
+}
+
+#[test]
+fn in_memory_repository_keeps_the_contract() {
+    check_contract(&InMemoryFruitRepository::new(seed()));
+}
+
+#[test]
+fn json_file_repository_keeps_the_contract() {
+    let dir = TempDir::new().unwrap();
+    let repository = JsonFileFruitRepository::open(dir.path().join("fruits.json"), seed()).unwrap();
+    check_contract(&repository);
+}
+
+#[test]
+fn sqlite_repository_keeps_the_contract() {
+    check_contract(&SqliteFruitRepository::in_memory(seed()).unwrap());
+}
+
+// Changes a freshly seeded repository, then checks what a reopened one sees.
+fn check_reopened(open: impl Fn(Vec<Fruit>) -> Box<dyn FruitRepository>) {
+    {
+        let repository = open(seed());
+        repository.create("plum".to_string()).unwrap();
+        repository.delete(6, &Precondition::Unconditional).unwrap();
+        repository
+            .put(fruit(5, "nashi"), &Precondition::Unconditional)
+            .unwrap();
+        repository.remove_events(1).unwrap();
+    }
+
+    // The seed only applies to a new store.
+    let repository = open(vec![fruit(1, "quince")]);
+
+    let (fruits, total) = repository
+        .list(&FruitQuery {
+            limit: 10,
+            ..FruitQuery::default()
+        })
+        .unwrap();
+    assert_eq!(total, 3);
+    assert_eq!((fruits[2].name.as_str(), fruits[2].version), ("nashi", 2));
+    assert_eq!(repository.create("kiwi".to_string()).unwrap().id, 7);
+    // Events not yet published are still in the outbox.
+    let sequences: Vec<u64> = repository
+        .pending_events(10)
+        .unwrap()
+        .iter()
+        .map(|event| event.sequence)
+        .collect();
+    assert_eq!(sequences, vec![2, 3, 4]);
+    // So is the version of the deleted plum.
+    let (_, plum) = repository
+        .put(fruit(6, "plum"), &Precondition::Unconditional)
+        .unwrap();
+    assert_eq!(plum.version, 2);
+}
+
+#[test]
+fn json_file_repository_survives_a_restart() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("fruits.json");
+    check_reopened(|seed| Box::new(JsonFileFruitRepository::open(&path, seed).unwrap()));
+}
+
+#[test]
+fn sqlite_repository_survives_a_restart() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("fruits.db");
+    check_reopened(|seed| Box::new(SqliteFruitRepository::open(&path, seed).unwrap()));
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::Actor;
+use actix_rt::time::{sleep, timeout};
+use futures::future::join_all;
+use gateway::{
+    actor::GlobalActor,
+    service::{ServiceClient, ServiceError},
+};
+use protocol::messages::GetPerson;
+use transport::Transport;
+
+use common::{in_memory_transport, RecordingTransport};
+
+// How many requests the registry holds before it evicts the oldest.
+const IN_FLIGHT: usize = 500;
+
+fn client(transport: Arc<dyn Transport>, reply_timeout: Duration) -> ServiceClient {
+    ServiceClient::new(
+        GlobalActor::new().start(),
+        transport,
+        "from_service.test".to_string(),
+    )
+    .with_reply_timeout(reply_timeout)
+}
+
+#[actix_web::test]
+async fn request_evicted_by_newer_ones_is_overloaded_not_timed_out() {
+    let client = client(in_memory_transport(), Duration::from_millis(500));
+
+    let requests = (0..=IN_FLIGHT as i32).map(|number| client.request(GetPerson { number }));
+    let results = join_all(requests).await;
+
+    let codes: Vec<_> = results
+        .iter()
+        .map(|result| result.as_ref().unwrap_err().code())
+        .collect();
+    assert_eq!(codes[0], "GATEWAY_OVERLOADED");
+    assert!(codes[1..].iter().all(|code| *code == "BACKEND_TIMEOUT"));
+}
+
+#[actix_web::test]
+async fn requests_that_were_not_sent_do_not_evict_others() {
+    let transport = Arc::new(RecordingTransport::new(in_memory_transport()).failing_after(1));
+    let client = client(transport.clone(), Duration::from_millis(500));
+    let waiting = {
+        let client = client.clone();
+        actix_rt::spawn(async move { client.request(GetPerson { number: 1 }).await })
+    };
+    while transport.published() == 0 {
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    for number in 0..IN_FLIGHT as i32 {
+        let result = client.request(GetPerson { number }).await;
+        assert!(matches!(
+            result,
+            Err(ServiceError::BrokerUnavailable { .. })
+        ));
+    }
+
+    let result = waiting.await.unwrap();
+    assert!(matches!(result, Err(ServiceError::BackendTimeout { .. })));
+}
+
+#[actix_web::test]
+async fn requests_their_callers_stopped_waiting_for_do_not_evict_others() {
+    let transport = Arc::new(RecordingTransport::new(in_memory_transport()));
+    let client = client(transport.clone(), Duration::from_millis(500));
+    let waiting = {
+        let client = client.clone();
+        actix_rt::spawn(async move { client.request(GetPerson { number: 1 }).await })
+    };
+    while transport.published() == 0 {
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    // A round at a time, so the registry never holds more than it can.
+    for round in 0..5 {
+        let requests = (0..IN_FLIGHT as i32 / 5).map(|number| {
+            timeout(
+                Duration::from_millis(20),
+                client.request(GetPerson {
+                    number: round * IN_FLIGHT as i32 + number,
+                }),
+            )
+        });
+        assert!(join_all(requests).await.iter().all(Result::is_err));
+    }
+
+    let result = waiting.await.unwrap();
+    assert!(matches!(result, Err(ServiceError::BackendTimeout { .. })));
+}
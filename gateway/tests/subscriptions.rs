@@ -0,0 +1,251 @@
+mod common;
+
+use std::pin::Pin;
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::{dev::Payload, http::StatusCode, test, web, web::Bytes, App};
+use async_graphql::Response;
+use futures::future::poll_fn;
+use futures::{poll, stream, Stream, StreamExt};
+use serde_json::{json, Value};
+
+use common::{graphql, start, REPLY_TIMEOUT};
+use gateway::{graphql::MySchema, routes::configure};
+
+// Polls the subscription once, so it is listening before the test changes anything.
+async fn subscribe<'a>(
+    schema: &'a MySchema,
+    query: &'a str,
+) -> impl Stream<Item = Response> + Unpin + 'a {
+    let mut stream = Box::pin(schema.execute_stream(query));
+    assert!(poll!(stream.next()).is_pending());
+    stream
+}
+
+async fn next_change(stream: &mut (impl Stream<Item = Response> + Unpin)) -> Value {
+    let response = actix_rt::time::timeout(REPLY_TIMEOUT, stream.next())
+        .await
+        .expect("no person change arrived")
+        .unwrap();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    response.data.into_json().unwrap()
+}
+
+#[actix_web::test]
+async fn mutations_are_sent_to_subscribers() {
+    let gateway = start().await;
+    let mut changes = subscribe(
+        &gateway.schema,
+        "subscription { personChanged { kind, person { name, id { number } } } }",
+    )
+    .await;
+
+    graphql(
+        gateway.clone(),
+        r#"mutation {
+            createPerson(name: "Dana", department: "Finance") { name }
+            updatePerson(number: 4, name: "Danielle") { name }
+            deletePerson(number: 4) { name }
+        }"#,
+    )
+    .await;
+
+    assert_eq!(
+        next_change(&mut changes).await,
+        json!({ "personChanged": {
+            "kind": "CREATED", "person": { "name": "Dana", "id": { "number": 4 } }
+        } })
+    );
+    assert_eq!(
+        next_change(&mut changes).await,
+        json!({ "personChanged": {
+            "kind": "UPDATED", "person": { "name": "Danielle", "id": { "number": 4 } }
+        } })
+    );
+    assert_eq!(
+        next_change(&mut changes).await,
+        json!({ "personChanged": {
+            "kind": "DELETED", "person": { "name": "Danielle", "id": { "number": 4 } }
+        } })
+    );
+}
+
+#[actix_web::test]
+async fn department_subscribers_see_persons_arrive_and_leave() {
+    let gateway = start().await;
+    let mut changes = subscribe(
+        &gateway.schema,
+        r#"subscription { personChanged(department: "Finance") {
+            kind, person { name, id { department } }, previous { id { department } }
+        } }"#,
+    )
+    .await;
+
+    graphql(
+        gateway.clone(),
+        r#"mutation {
+            eve: createPerson(name: "Eve", department: "Operations") { name }
+            updatePerson(number: 2, department: "Operations") { name }
+            dana: createPerson(name: "Dana", department: "Finance") { name }
+        }"#,
+    )
+    .await;
+
+    assert_eq!(
+        next_change(&mut changes).await,
+        json!({ "personChanged": {
+            "kind": "UPDATED",
+            "person": { "name": "Bob", "id": { "department": "Operations" } },
+            "previous": { "id": { "department": "Finance" } }
+        } })
+    );
+    assert_eq!(
+        next_change(&mut changes).await,
+        json!({ "personChanged": {
+            "kind": "CREATED",
+            "person": { "name": "Dana", "id": { "department": "Finance" } },
+            "previous": null
+        } })
+    );
+}
+
+#[actix_web::test]
+async fn rejected_mutations_are_not_sent() {
+    let gateway = start().await;
+    let mut changes = subscribe(&gateway.schema, "subscription { personChanged { kind } }").await;
+
+    graphql(
+        gateway.clone(),
+        r#"mutation {
+            blank: createPerson(name: "", department: "Finance") { name }
+            deletePerson(number: 99) { name }
+            dana: createPerson(name: "Dana", department: "Finance") { name }
+        }"#,
+    )
+    .await;
+
+    assert_eq!(
+        next_change(&mut changes).await,
+        json!({ "personChanged": { "kind": "CREATED" } })
+    );
+}
+
+// A text frame as a client sends it: masked, with a key of zeroes so the payload reads as is.
+fn client_frame(message: &Value) -> Bytes {
+    let payload = message.to_string().into_bytes();
+    let mut frame = vec![0x81];
+    if payload.len() < 126 {
+        frame.push(0x80 | payload.len() as u8);
+    } else {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    }
+    frame.extend_from_slice(&[0; 4]);
+    frame.extend_from_slice(&payload);
+    frame.into()
+}
+
+// The server's end of a socket, read frame by frame.
+struct Socket {
+    body: BoxBody,
+    received: Vec<u8>,
+}
+
+impl Socket {
+    // The next text message the server sends, skipping its pings.
+    async fn next_message(&mut self) -> Value {
+        loop {
+            while let Some((opcode, payload)) = self.take_frame() {
+                if opcode == 0x1 {
+                    return serde_json::from_slice(&payload).unwrap();
+                }
+            }
+            let chunk = actix_rt::time::timeout(
+                REPLY_TIMEOUT,
+                poll_fn(|cx| Pin::new(&mut self.body).poll_next(cx)),
+            )
+            .await
+            .expect("no message arrived")
+            .expect("the socket was closed")
+            .unwrap();
+            self.received.extend_from_slice(&chunk);
+        }
+    }
+
+    // Server frames are never masked, and these tests' messages need no 64-bit lengths.
+    fn take_frame(&mut self) -> Option<(u8, Vec<u8>)> {
+        let (length, header) = match *self.received.get(1)? & 0x7f {
+            126 => {
+                let length = self.received.get(2..4)?;
+                (u16::from_be_bytes([length[0], length[1]]) as usize, 4)
+            }
+            127 => panic!("unexpectedly long frame"),
+            length => (length as usize, 2),
+        };
+        if self.received.len() < header + length {
+            return None;
+        }
+        let opcode = self.received[0] & 0x0f;
+        let frame: Vec<u8> = self.received.drain(..header + length).collect();
+        Some((opcode, frame[header..].to_vec()))
+    }
+}
+
+#[actix_web::test]
+async fn queries_are_served_over_graphql_transport_ws() {
+    let gateway = start().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(gateway.schema))
+            .app_data(web::Data::new(gateway.service_client))
+            .configure(configure),
+    )
+    .await;
+
+    let messages: Vec<_> = [
+        json!({ "type": "connection_init" }),
+        json!({ "id": "1", "type": "subscribe", "payload": {
+            "query": "{ a: person(number: 1) { name } b: person(number: 2) { name } }"
+        } }),
+    ]
+    .iter()
+    .map(|message| Ok(client_frame(message)))
+    .collect();
+    // The socket stays open once the messages are sent, as a client's would.
+    let messages: Pin<Box<dyn Stream<Item = _>>> =
+        Box::pin(stream::iter(messages).chain(stream::pending()));
+    let (req, _) = test::TestRequest::get()
+        .uri("/graphql")
+        .insert_header(("upgrade", "websocket"))
+        .insert_header(("connection", "upgrade"))
+        .insert_header(("sec-websocket-version", "13"))
+        .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+        .insert_header(("sec-websocket-protocol", "graphql-transport-ws"))
+        .to_request()
+        .replace_payload(Payload::from(messages));
+    let response = test::call_service(&app, req).await;
+
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(
+        response.headers().get("sec-websocket-protocol").unwrap(),
+        "graphql-transport-ws"
+    );
+    let mut socket = Socket {
+        body: response.into_body(),
+        received: vec![],
+    };
+    assert_eq!(
+        socket.next_message().await,
+        json!({ "type": "connection_ack" })
+    );
+    assert_eq!(
+        socket.next_message().await,
+        json!({ "id": "1", "type": "next", "payload": { "data": {
+            "a": { "name": "Alice" }, "b": { "name": "Bob" }
+        } } })
+    );
+    assert_eq!(
+        socket.next_message().await,
+        json!({ "id": "1", "type": "complete" })
+    );
+}
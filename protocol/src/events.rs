@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Fruit, Person};
+
+/// The topic the user-service publishes a `PersonChanged` to after every change it applies.
+///
+/// Every gateway instance reads all of it, in a consumer group of its own.
+pub const PERSON_CHANGES_TOPIC: &str = "person_changes";
+
+/// The topic the gateway publishes a `FruitEvent` to after every change to a fruit, unless it is
+/// configured with another.
+pub const DEFAULT_FRUIT_EVENTS_TOPIC: &str = "fruit_events";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+pub enum PersonChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct PersonChanged {
+    pub kind: PersonChangeKind,
+    /// The person after the change, or as it was before it was deleted.
+    pub person: Person,
+    /// The person before an update; `None` for creations and deletions.
+    pub previous: Option<Person>,
+}
+
+impl PersonChanged {
+    /// Whether the change concerns the department, including a person moving into or out of it.
+    pub fn touches_department(&self, department: &str) -> bool {
+        std::iter::once(&self.person)
+            .chain(&self.previous)
+            .any(|person| person.id.department.eq_ignore_ascii_case(department))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FruitEventKind {
+    FruitCreated,
+    FruitUpdated,
+    FruitDeleted,
+}
+
+/// A change to a fruit, as published to the fruit events topic with the fruit id as its key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FruitEvent {
+    /// Numbers the events of a repository from 1, in the order of the changes. An event can be
+    /// published more than once, so consumers should drop one whose number they have seen.
+    pub sequence: u64,
+    #[serde(rename = "type")]
+    pub kind: FruitEventKind,
+    /// The fruit before the change; `None` for creations.
+    pub before: Option<Fruit>,
+    /// The fruit after the change; `None` for deletions.
+    pub after: Option<Fruit>,
+}
+
+impl FruitEvent {
+    /// The event of a change from `before` to `after`, where `None` is no fruit, or `None` if
+    /// there was no fruit either side of it.
+    pub fn of_change(sequence: u64, before: Option<Fruit>, after: Option<Fruit>) -> Option<Self> {
+        let kind = match (&before, &after) {
+            (None, Some(_)) => FruitEventKind::FruitCreated,
+            (Some(_), Some(_)) => FruitEventKind::FruitUpdated,
+            (Some(_), None) => FruitEventKind::FruitDeleted,
+            (None, None) => return None,
+        };
+        Some(FruitEvent {
+            sequence,
+            kind,
+            before,
+            after,
+        })
+    }
+
+    /// The id of the changed fruit, which keys the event so the events of one fruit stay in order.
+    pub fn fruit_id(&self) -> u32 {
+        self.after
+            .as_ref()
+            .or(self.before.as_ref())
+            .map(|fruit| fruit.id)
+            .expect("an event has a fruit before or after the change")
+    }
+}
@@ -0,0 +1,4 @@
+pub mod events;
+pub mod messages;
+pub mod models;
+pub mod validation;
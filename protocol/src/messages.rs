@@ -0,0 +1,323 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::Person;
+
+/// The most persons a `PageRequest` may ask for.
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Command {
+    GetPerson {
+        number: i32,
+    },
+    /// The persons with any of the numbers; numbers no person has are left out. They are
+    /// answered with a `PersonsByIds` in no particular order.
+    GetPersonsByIds {
+        numbers: Vec<i32>,
+    },
+    /// The page of the persons matching every filter that is set.
+    GetPersons {
+        department: Option<String>,
+        name_contains: Option<String>,
+        page: PageRequest,
+    },
+    /// The service assigns the new person's number.
+    CreatePerson {
+        name: String,
+        department: String,
+    },
+    /// Changes only the fields that are set.
+    UpdatePerson {
+        number: i32,
+        name: Option<String>,
+        department: Option<String>,
+    },
+    DeletePerson {
+        number: i32,
+    },
+}
+
+/// A page of a listing in order of person number, bounded by the numbers of the persons either
+/// side of it, so a page stays put while persons are added and removed elsewhere.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PageRequest {
+    /// Only persons numbered above this.
+    pub after: Option<i32>,
+    /// Only persons numbered below this.
+    pub before: Option<i32>,
+    /// How many persons, at most `MAX_PAGE_SIZE`.
+    pub limit: u32,
+    /// Whether the page is the last `limit` persons between the bounds rather than the first.
+    pub from_end: bool,
+}
+
+/// The persons of a `PageRequest`, in order of number.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PersonPage {
+    pub persons: Vec<Person>,
+    /// Whether persons matching the filters are numbered below the page.
+    pub has_previous: bool,
+    /// Whether persons matching the filters are numbered above the page.
+    pub has_next: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceRequest {
+    pub request_id: String,
+    pub command: Command,
+    /// The topic the requesting gateway instance listens on for its replies.
+    #[serde(default)]
+    pub reply_to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ResponseMessageDto {
+    /// `None` if there is no person with the requested number.
+    Person {
+        person: Option<Person>,
+    },
+    Persons {
+        page: PersonPage,
+    },
+    /// The persons a `GetPersonsByIds` asked for, in no particular order.
+    PersonsByIds {
+        persons: Vec<Person>,
+    },
+    /// The service refused to apply the command.
+    Rejected {
+        error: CommandError,
+    },
+}
+
+impl ResponseMessageDto {
+    pub fn response_type(&self) -> &'static str {
+        match self {
+            ResponseMessageDto::Person { .. } => "Person",
+            ResponseMessageDto::Persons { .. } => "Persons",
+            ResponseMessageDto::PersonsByIds { .. } => "PersonsByIds",
+            ResponseMessageDto::Rejected { .. } => "Rejected",
+        }
+    }
+}
+
+/// Why the service refused to apply a command.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// `field` of the command has a value the service does not accept.
+    Validation { field: String, message: String },
+    /// There is no person with the given number.
+    NotFound { number: i32 },
+    /// Every person number up to `i32::MAX` has been used, so no more persons can be created.
+    NumbersExhausted,
+    /// The service could not read or write its data; the same command may succeed later.
+    Storage { message: String },
+}
+
+impl CommandError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CommandError::Validation { .. } => "VALIDATION_ERROR",
+            CommandError::NotFound { .. } => "NOT_FOUND",
+            CommandError::NumbersExhausted => "NUMBERS_EXHAUSTED",
+            CommandError::Storage { .. } => "STORAGE_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Validation { field, message } => write!(f, "Invalid {field}: {message}"),
+            CommandError::NotFound { number } => {
+                write!(f, "There is no person with number {number}")
+            }
+            CommandError::NumbersExhausted => write!(
+                f,
+                "Every person number up to {} has been used, so no more persons can be created",
+                i32::MAX
+            ),
+            CommandError::Storage { message } => write!(f, "Storage error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseMessageDtoWrapper {
+    pub response_message_dto: ResponseMessageDto,
+    pub request_id: String,
+    pub response_type: String,
+}
+
+impl ResponseMessageDtoWrapper {
+    pub fn new(request_id: String, response_message_dto: ResponseMessageDto) -> Self {
+        Self {
+            response_type: response_message_dto.response_type().to_string(),
+            response_message_dto,
+            request_id,
+        }
+    }
+}
+
+/// A request the user-service knows how to answer, together with the type of its reply.
+///
+/// Adding a command means adding its `Command` and `ResponseMessageDto` variants, a request
+/// type, and an implementation of this trait that picks the reply out of the response.
+/// `ResponseMessageDto::Rejected` answers any request, so implementations need not handle it.
+pub trait ServiceQuery: Into<Command> {
+    type Response;
+
+    /// Returns `None` if the service answered with a different kind of response.
+    fn from_response(response: ResponseMessageDto) -> Option<Self::Response>;
+}
+
+pub struct GetPerson {
+    pub number: i32,
+}
+
+impl From<GetPerson> for Command {
+    fn from(query: GetPerson) -> Self {
+        Command::GetPerson {
+            number: query.number,
+        }
+    }
+}
+
+impl ServiceQuery for GetPerson {
+    type Response = Option<Person>;
+
+    fn from_response(response: ResponseMessageDto) -> Option<Option<Person>> {
+        match response {
+            ResponseMessageDto::Person { person } => Some(person),
+            _ => None,
+        }
+    }
+}
+
+pub struct GetPersonsByIds {
+    pub numbers: Vec<i32>,
+}
+
+impl From<GetPersonsByIds> for Command {
+    fn from(query: GetPersonsByIds) -> Self {
+        Command::GetPersonsByIds {
+            numbers: query.numbers,
+        }
+    }
+}
+
+impl ServiceQuery for GetPersonsByIds {
+    type Response = Vec<Person>;
+
+    fn from_response(response: ResponseMessageDto) -> Option<Vec<Person>> {
+        match response {
+            ResponseMessageDto::PersonsByIds { persons } => Some(persons),
+            _ => None,
+        }
+    }
+}
+
+pub struct GetPersons {
+    pub department: Option<String>,
+    pub name_contains: Option<String>,
+    pub page: PageRequest,
+}
+
+impl From<GetPersons> for Command {
+    fn from(query: GetPersons) -> Self {
+        Command::GetPersons {
+            department: query.department,
+            name_contains: query.name_contains,
+            page: query.page,
+        }
+    }
+}
+
+impl ServiceQuery for GetPersons {
+    type Response = PersonPage;
+
+    fn from_response(response: ResponseMessageDto) -> Option<PersonPage> {
+        match response {
+            ResponseMessageDto::Persons { page } => Some(page),
+            _ => None,
+        }
+    }
+}
+
+pub struct CreatePerson {
+    pub name: String,
+    pub department: String,
+}
+
+impl From<CreatePerson> for Command {
+    fn from(mutation: CreatePerson) -> Self {
+        Command::CreatePerson {
+            name: mutation.name,
+            department: mutation.department,
+        }
+    }
+}
+
+impl ServiceQuery for CreatePerson {
+    type Response = Person;
+
+    fn from_response(response: ResponseMessageDto) -> Option<Person> {
+        person_from_response(response)
+    }
+}
+
+pub struct UpdatePerson {
+    pub number: i32,
+    pub name: Option<String>,
+    pub department: Option<String>,
+}
+
+impl From<UpdatePerson> for Command {
+    fn from(mutation: UpdatePerson) -> Self {
+        Command::UpdatePerson {
+            number: mutation.number,
+            name: mutation.name,
+            department: mutation.department,
+        }
+    }
+}
+
+impl ServiceQuery for UpdatePerson {
+    type Response = Person;
+
+    fn from_response(response: ResponseMessageDto) -> Option<Person> {
+        person_from_response(response)
+    }
+}
+
+pub struct DeletePerson {
+    pub number: i32,
+}
+
+impl From<DeletePerson> for Command {
+    fn from(mutation: DeletePerson) -> Self {
+        Command::DeletePerson {
+            number: mutation.number,
+        }
+    }
+}
+
+impl ServiceQuery for DeletePerson {
+    /// The person as it was before it was deleted.
+    type Response = Person;
+
+    fn from_response(response: ResponseMessageDto) -> Option<Person> {
+        person_from_response(response)
+    }
+}
+
+// Mutations that succeed always answer with the person they changed.
+fn person_from_response(response: ResponseMessageDto) -> Option<Person> {
+    match response {
+        ResponseMessageDto::Person { person } => person,
+        _ => None,
+    }
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Id {
+    pub number: i32,
+    pub department: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Person {
+    pub name: String,
+    pub id: Id,
+}
+
+/// A fruit, as the gateway serves it and publishes its changes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Fruit {
+    pub id: u32,
+    pub name: String,
+    /// Assigned by the server and bumped on every replace; also the fruit's `ETag`. Ignored in
+    /// request bodies.
+    #[serde(default)]
+    pub version: u64,
+}
@@ -0,0 +1,12 @@
+/// Trims a text field of a request and checks that what is left is neither blank nor longer
+/// than `max_length` characters. The error says what is wrong, to be reported against the field.
+pub fn trimmed(value: &str, max_length: usize) -> Result<&str, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        Err("must not be blank".to_string())
+    } else if value.chars().count() > max_length {
+        Err(format!("must be at most {max_length} characters"))
+    } else {
+        Ok(value)
+    }
+}
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{info, warn};
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+use rdkafka::client::{ClientContext, DefaultClientContext};
+use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
+use rdkafka::consumer::stream_consumer::StreamConsumer;
+use rdkafka::consumer::{Consumer, ConsumerContext, Rebalance};
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::message::{Headers, Message};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::topic_partition_list::TopicPartitionList;
+
+use crate::{Envelope, StartFrom, Subscription, Transport, TransportError};
+
+impl From<KafkaError> for TransportError {
+    fn from(e: KafkaError) -> Self {
+        TransportError::Backend(e.to_string())
+    }
+}
+
+// A context can be used to change the behavior of producers and consumers by adding callbacks
+// that will be executed by librdkafka.
+// This particular context sets up custom callbacks to log rebalancing events.
+pub struct CustomContext;
+
+impl ClientContext for CustomContext {}
+
+impl ConsumerContext for CustomContext {
+    fn pre_rebalance(&self, rebalance: &Rebalance) {
+        info!("Pre rebalance {:?}", rebalance);
+    }
+
+    fn post_rebalance(&self, rebalance: &Rebalance) {
+        info!("Post rebalance {:?}", rebalance);
+    }
+
+    fn commit_callback(&self, result: KafkaResult<()>, _offsets: &TopicPartitionList) {
+        info!("Committing offsets: {:?}", result);
+    }
+}
+
+// A type alias with your custom consumer can be created for convenience.
+type LoggingConsumer = StreamConsumer<CustomContext>;
+
+/// A transport backed by a Kafka cluster.
+#[derive(Clone)]
+pub struct KafkaTransport {
+    brokers: String,
+    producer: FutureProducer,
+}
+
+impl KafkaTransport {
+    pub fn new(brokers: &str) -> Result<KafkaTransport, KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("produce.offset.report", "true")
+            .set("message.timeout.ms", "5000")
+            .create()?;
+        Ok(KafkaTransport {
+            brokers: brokers.to_string(),
+            producer,
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for KafkaTransport {
+    async fn publish(&self, topic: &str, key: &str, payload: &str) -> Result<(), TransportError> {
+        self.producer
+            .send(
+                FutureRecord::to(topic).payload(payload).key(key),
+                Duration::from_secs(0),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|(e, _)| e.into())
+    }
+
+    async fn subscribe(
+        &self,
+        group_id: &str,
+        topics: &[&str],
+        start_from: StartFrom,
+    ) -> Result<Box<dyn Subscription>, TransportError> {
+        let offset_reset = match start_from {
+            StartFrom::Earliest => "earliest",
+            StartFrom::Latest => "latest",
+        };
+        let consumer: LoggingConsumer = ClientConfig::new()
+            .set("group.id", group_id)
+            .set("bootstrap.servers", &self.brokers)
+            .set("enable.partition.eof", "false")
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "true")
+            // Only offsets of acknowledged messages are committed.
+            .set("enable.auto.offset.store", "false")
+            //.set("statistics.interval.ms", "30000")
+            .set("auto.offset.reset", offset_reset)
+            .set_log_level(RDKafkaLogLevel::Debug)
+            .create_with_context(CustomContext)?;
+        consumer.subscribe(topics)?;
+        Ok(Box::new(KafkaSubscription { consumer }))
+    }
+
+    async fn create_topic(&self, topic: &str) -> Result<(), TransportError> {
+        let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+            .set("bootstrap.servers", &self.brokers)
+            .create()?;
+        let new_topic = NewTopic::new(topic, 1, TopicReplication::Fixed(1));
+        let results = admin
+            .create_topics(&[new_topic], &AdminOptions::new())
+            .await?;
+        for result in results {
+            match result {
+                Ok(topic) => info!("Created topic {topic}"),
+                Err((topic, RDKafkaErrorCode::TopicAlreadyExists)) => {
+                    info!("Topic {topic} already exists")
+                }
+                Err((_, code)) => return Err(KafkaError::AdminOp(code).into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct KafkaSubscription {
+    consumer: LoggingConsumer,
+}
+
+#[async_trait]
+impl Subscription for KafkaSubscription {
+    async fn next(&mut self) -> Result<Envelope, TransportError> {
+        let m = self.consumer.recv().await?;
+        let payload = match m.payload_view::<str>() {
+            None => "",
+            Some(Ok(s)) => s,
+            Some(Err(e)) => {
+                warn!("Error while deserializing message payload: {:?}", e);
+                ""
+            }
+        };
+        info!("key: '{:?}', payload: '{}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
+              m.key(), payload, m.topic(), m.partition(), m.offset(), m.timestamp());
+        if let Some(headers) = m.headers() {
+            for i in 0..headers.count() {
+                let header = headers.get(i).unwrap();
+                info!("  Header {:#?}: {:?}", header.0, header.1);
+            }
+        }
+        Ok(Envelope {
+            topic: m.topic().to_string(),
+            key: m.key().map(|k| String::from_utf8_lossy(k).into_owned()),
+            payload: payload.to_string(),
+            partition: m.partition(),
+            offset: m.offset(),
+        })
+    }
+
+    fn ack(&self, envelope: &Envelope) -> Result<(), TransportError> {
+        self.consumer
+            .store_offset(&envelope.topic, envelope.partition, envelope.offset)
+            .map_err(|e| e.into())
+    }
+}
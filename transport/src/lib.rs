@@ -0,0 +1,73 @@
+use std::fmt;
+
+use async_trait::async_trait;
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod memory;
+
+/// A message received from a topic.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub topic: String,
+    pub key: Option<String>,
+    pub payload: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// Where a consumer group that has not acknowledged anything yet starts reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartFrom {
+    /// Messages published before the group subscribed are delivered too.
+    Earliest,
+    /// Only messages published after the group subscribed are delivered.
+    Latest,
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    /// The subscription will not deliver any more messages.
+    Closed,
+    /// The backend rejected the operation.
+    Backend(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Closed => write!(f, "The subscription is closed"),
+            TransportError::Backend(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// Publishes messages to topics and subscribes consumer groups to them.
+///
+/// Each message published to a topic is delivered once to every consumer group subscribed to
+/// it, to one of the group's subscriptions.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn publish(&self, topic: &str, key: &str, payload: &str) -> Result<(), TransportError>;
+
+    async fn subscribe(
+        &self,
+        group_id: &str,
+        topics: &[&str],
+        start_from: StartFrom,
+    ) -> Result<Box<dyn Subscription>, TransportError>;
+
+    /// Makes sure the topic exists before anyone publishes to it.
+    async fn create_topic(&self, topic: &str) -> Result<(), TransportError>;
+}
+
+#[async_trait]
+pub trait Subscription: Send {
+    /// Waits for the next message on any of the subscribed topics.
+    async fn next(&mut self) -> Result<Envelope, TransportError>;
+
+    /// Marks the message as handled, so the group does not receive it again after a restart.
+    fn ack(&self, envelope: &Envelope) -> Result<(), TransportError>;
+}
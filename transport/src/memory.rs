@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::{Envelope, StartFrom, Subscription, Transport, TransportError};
+
+/// A transport that delivers messages over in-process channels.
+///
+/// Clones share the same topics, so a gateway and a user-service holding clones of one
+/// `InMemoryTransport` talk to each other exactly as they would over Kafka, without a broker.
+#[derive(Clone, Default)]
+pub struct InMemoryTransport {
+    topics: Arc<Mutex<HashMap<String, Topic>>>,
+}
+
+#[derive(Default)]
+struct Topic {
+    // Every message published so far, for groups that subscribe with `StartFrom::Earliest`.
+    log: Vec<Envelope>,
+    groups: HashMap<String, Group>,
+}
+
+#[derive(Default)]
+struct Group {
+    subscribers: Vec<UnboundedSender<Envelope>>,
+    // Round-robins messages between the subscriptions of the group.
+    next_subscriber: usize,
+}
+
+impl Group {
+    fn deliver(&mut self, envelope: Envelope) {
+        let mut envelope = envelope;
+        while !self.subscribers.is_empty() {
+            let index = self.next_subscriber % self.subscribers.len();
+            match self.subscribers[index].send(envelope) {
+                Ok(()) => {
+                    self.next_subscriber = index + 1;
+                    return;
+                }
+                // The subscription was dropped, so try the next one in the group.
+                Err(failed) => {
+                    self.subscribers.remove(index);
+                    envelope = failed.0;
+                }
+            }
+        }
+    }
+}
+
+impl InMemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Transport for InMemoryTransport {
+    async fn publish(&self, topic: &str, key: &str, payload: &str) -> Result<(), TransportError> {
+        let mut topics = self.topics.lock().unwrap();
+        let topic_state = topics.entry(topic.to_string()).or_default();
+        let envelope = Envelope {
+            topic: topic.to_string(),
+            key: Some(key.to_string()),
+            payload: payload.to_string(),
+            partition: 0,
+            offset: topic_state.log.len() as i64,
+        };
+        topic_state.log.push(envelope.clone());
+        for group in topic_state.groups.values_mut() {
+            group.deliver(envelope.clone());
+        }
+        Ok(())
+    }
+
+    async fn subscribe(
+        &self,
+        group_id: &str,
+        topics: &[&str],
+        start_from: StartFrom,
+    ) -> Result<Box<dyn Subscription>, TransportError> {
+        let (tx, rx) = unbounded_channel();
+        let mut all_topics = self.topics.lock().unwrap();
+        for topic in topics {
+            let topic_state = all_topics.entry(topic.to_string()).or_default();
+            let is_new_group = !topic_state.groups.contains_key(group_id);
+            let group = topic_state.groups.entry(group_id.to_string()).or_default();
+            if is_new_group && start_from == StartFrom::Earliest {
+                for envelope in &topic_state.log {
+                    // The receiver is alive until this function returns.
+                    let _ = tx.send(envelope.clone());
+                }
+            }
+            group.subscribers.push(tx.clone());
+        }
+        Ok(Box::new(InMemorySubscription { rx }))
+    }
+
+    async fn create_topic(&self, topic: &str) -> Result<(), TransportError> {
+        self.topics
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_default();
+        Ok(())
+    }
+}
+
+pub struct InMemorySubscription {
+    rx: UnboundedReceiver<Envelope>,
+}
+
+#[async_trait]
+impl Subscription for InMemorySubscription {
+    async fn next(&mut self) -> Result<Envelope, TransportError> {
+        self.rx.recv().await.ok_or(TransportError::Closed)
+    }
+
+    // Messages are removed from the channel when they are received, so there is nothing left
+    // to acknowledge.
+    fn ack(&self, _envelope: &Envelope) -> Result<(), TransportError> {
+        Ok(())
+    }
+}
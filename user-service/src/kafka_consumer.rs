@@ -1,176 +1,166 @@
-use std::time::Duration;
+use std::sync::Arc;
 
-use log::{info, warn};
+use log::warn;
 
-use rdkafka::client::ClientContext;
-use rdkafka::config::{ClientConfig, RDKafkaLogLevel};
-use rdkafka::consumer::stream_consumer::StreamConsumer;
-use rdkafka::consumer::{CommitMode, Consumer, ConsumerContext, Rebalance};
-use rdkafka::error::{KafkaError, KafkaResult};
-use rdkafka::message::{Headers, Message};
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::topic_partition_list::TopicPartitionList;
-
-use crate::models::{
-    Command, Id, Person, ResponseMessageDto, ResponseMessageDtoWrapper, ServiceRequest,
+use protocol::events::{PersonChangeKind, PersonChanged, PERSON_CHANGES_TOPIC};
+use protocol::messages::{
+    Command, CommandError, ResponseMessageDto, ResponseMessageDtoWrapper, ServiceRequest,
 };
+use protocol::models::Person;
+use transport::{Envelope, Subscription, Transport, TransportError};
 
-// A context can be used to change the behavior of producers and consumers by adding callbacks
-// that will be executed by librdkafka.
-// This particular context sets up custom callbacks to log rebalancing events.
-pub struct CustomContext;
-
-impl ClientContext for CustomContext {}
-
-impl ConsumerContext for CustomContext {
-    fn pre_rebalance(&self, rebalance: &Rebalance) {
-        info!("Pre rebalance {:?}", rebalance);
-    }
-
-    fn post_rebalance(&self, rebalance: &Rebalance) {
-        info!("Post rebalance {:?}", rebalance);
-    }
-
-    fn commit_callback(&self, result: KafkaResult<()>, _offsets: &TopicPartitionList) {
-        info!("Committing offsets: {:?}", result);
-    }
-}
-
-// A type alias with your custom consumer can be created for convenience.
-type LoggingConsumer = StreamConsumer<CustomContext>;
+use crate::store::{PersonStore, StoreError};
 
 pub struct IngestConsumer {
-    pub consumer: LoggingConsumer,
-    pub producer: FutureProducer,
+    pub subscription: Box<dyn Subscription>,
+    pub transport: Arc<dyn Transport>,
+    pub store: PersonStore,
 }
 
+// Replies go to the topic named in the request; this is only used for requests that do not name one.
 const PUBLISH_TO: &str = "from_service";
 impl IngestConsumer {
     pub fn new(
-        brokers: String,
-        group_id: String,
-        topics: Vec<String>,
-        producer: FutureProducer,
-    ) -> Result<IngestConsumer, KafkaError> {
-        let context = CustomContext;
-
-        let consumer: LoggingConsumer = ClientConfig::new()
-            .set("group.id", group_id)
-            .set("bootstrap.servers", brokers)
-            .set("enable.partition.eof", "false")
-            .set("session.timeout.ms", "6000")
-            .set("enable.auto.commit", "true")
-            //.set("statistics.interval.ms", "30000")
-            //.set("auto.offset.reset", "smallest")
-            .set_log_level(RDKafkaLogLevel::Debug)
-            .create_with_context(context)
-            .expect("Consumer creation failed");
-
-        let topics: Vec<&str> = topics.iter().map(String::as_str).collect();
-        consumer
-            .subscribe(&topics)
-            .expect("Can't subscribe to specified topics");
-        Ok(IngestConsumer { consumer, producer })
+        subscription: Box<dyn Subscription>,
+        transport: Arc<dyn Transport>,
+        store: PersonStore,
+    ) -> Self {
+        IngestConsumer {
+            subscription,
+            transport,
+            store,
+        }
     }
 
-    pub async fn run(&self) {
+    pub async fn run(&mut self) {
         loop {
-            match self.consumer.recv().await {
+            match self.subscription.next().await {
+                Err(TransportError::Closed) => break,
                 Err(e) => {
-                    warn!("Kafka error: {}", e);
+                    warn!("Transport error: {}", e);
                 }
                 Ok(m) => {
-                    let payload = match m.payload_view::<str>() {
-                        None => "",
-                        Some(Ok(s)) => s,
-                        Some(Err(e)) => {
-                            warn!("Error while deserializing message payload: {:?}", e);
-                            ""
-                        }
-                    };
-                    info!("key: '{:?}', payload: '{}', topic: {}, partition: {}, offset: {}, timestamp: {:?}",
-                          m.key(), payload, m.topic(), m.partition(), m.offset(), m.timestamp());
-                    if let Some(headers) = m.headers() {
-                        for i in 0..headers.count() {
-                            let header = headers.get(i).unwrap();
-                            info!("  Header {:#?}: {:?}", header.0, header.1);
-                        }
+                    self.handle_message(&m).await;
+                    if let Err(e) = self.subscription.ack(&m) {
+                        warn!("Could not acknowledge request: {e}");
                     }
-                    let ServiceRequest {
-                        request_id,
-                        command,
-                    }: ServiceRequest = serde_json::from_str(payload).unwrap();
-                    match command {
-                        Command::GetPerson => {
-                            let person = Person {
-                                name: "Alice - default".to_string(),
-                                id: Id {
-                                    number: 1,
-                                    department: "Executive".to_string(),
-                                },
-                            };
-                            let response_message_dto = ResponseMessageDto::Person { person };
-                            let message_dto_wrapper = ResponseMessageDtoWrapper {
-                                request_id: request_id.clone(),
-                                response_message_dto,
-                                response_type: "Person".to_string(),
-                            };
-                            let payload = serde_json::json!(message_dto_wrapper).to_string();
-                            self.producer
-                                .send(
-                                    FutureRecord::to(PUBLISH_TO)
-                                        .payload(&payload)
-                                        .key(&request_id.to_string()),
-                                    Duration::from_secs(0),
-                                )
-                                .await
-                                .unwrap();
-                        }
-                        Command::GetPersons => {
-                            let alice = Person {
-                                name: "Alice".to_string(),
-                                id: Id {
-                                    number: 1,
-                                    department: "Executive".to_string(),
-                                },
-                            };
-                            let bob = Person {
-                                name: "Bob".to_string(),
-                                id: Id {
-                                    number: 2,
-                                    department: "Finance".to_string(),
-                                },
-                            };
-                            let charlie = Person {
-                                name: "Charlie".to_string(),
-                                id: Id {
-                                    number: 3,
-                                    department: "Operations".to_string(),
-                                },
-                            };
-                            let persons = vec![alice, bob, charlie];
-                            let response_message_dto = ResponseMessageDto::Persons { persons };
-                            let message_dto_wrapper = ResponseMessageDtoWrapper {
-                                request_id: request_id.clone(),
-                                response_message_dto,
-                                response_type: "Persons".to_string(),
-                            };
-                            let payload = serde_json::json!(message_dto_wrapper).to_string();
-                            self.producer
-                                .send(
-                                    FutureRecord::to(PUBLISH_TO)
-                                        .payload(&payload)
-                                        .key(&request_id.to_string()),
-                                    Duration::from_secs(0),
-                                )
-                                .await
-                                .unwrap();
-                        }
-                    }
-
-                    self.consumer.commit_message(&m, CommitMode::Async).unwrap();
                 }
             };
         }
     }
+
+    async fn handle_message(&mut self, m: &Envelope) {
+        let ServiceRequest {
+            request_id,
+            command,
+            reply_to,
+        } = match serde_json::from_str(&m.payload) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Could not read request {}: {e}", m.payload);
+                return;
+            }
+        };
+        let reply_topic = reply_to.as_deref().unwrap_or(PUBLISH_TO);
+        let (response_message_dto, change) = handle_command(&mut self.store, command);
+        let message_dto_wrapper =
+            ResponseMessageDtoWrapper::new(request_id.clone(), response_message_dto);
+        let payload = serde_json::json!(message_dto_wrapper).to_string();
+        if let Err(e) = self
+            .transport
+            .publish(reply_topic, &request_id, &payload)
+            .await
+        {
+            warn!("Could not publish reply to request {request_id}: {e}");
+        }
+        if let Some(change) = change {
+            let key = change.person.id.number.to_string();
+            let payload = serde_json::json!(change).to_string();
+            if let Err(e) = self
+                .transport
+                .publish(PERSON_CHANGES_TOPIC, &key, &payload)
+                .await
+            {
+                warn!("Could not publish the change made by request {request_id}: {e}");
+            }
+        }
+    }
+}
+
+/// The reply to the command, and the change it made to the store, if any.
+pub fn handle_command(
+    store: &mut PersonStore,
+    command: Command,
+) -> (ResponseMessageDto, Option<PersonChanged>) {
+    match command {
+        Command::GetPerson { number } => match store.get(number) {
+            Ok(person) => (ResponseMessageDto::Person { person }, None),
+            Err(e) => (rejection(e), None),
+        },
+        Command::GetPersonsByIds { numbers } => match store.get_many(&numbers) {
+            Ok(persons) => (ResponseMessageDto::PersonsByIds { persons }, None),
+            Err(e) => (rejection(e), None),
+        },
+        Command::GetPersons {
+            department,
+            name_contains,
+            page,
+        } => match store.find_page(department.as_deref(), name_contains.as_deref(), &page) {
+            Ok(page) => (ResponseMessageDto::Persons { page }, None),
+            Err(e) => (rejection(e), None),
+        },
+        Command::CreatePerson { name, department } => changed(
+            PersonChangeKind::Created,
+            store.create(&name, &department),
+            None,
+        ),
+        Command::UpdatePerson {
+            number,
+            name,
+            department,
+        } => {
+            // The previous state lets subscribers see a person leave a department.
+            let previous = store.get(number).ok().flatten();
+            let result = store.update(number, name.as_deref(), department.as_deref());
+            changed(PersonChangeKind::Updated, result, previous)
+        }
+        Command::DeletePerson { number } => {
+            changed(PersonChangeKind::Deleted, store.delete(number), None)
+        }
+    }
+}
+
+fn changed(
+    kind: PersonChangeKind,
+    result: Result<Person, StoreError>,
+    previous: Option<Person>,
+) -> (ResponseMessageDto, Option<PersonChanged>) {
+    match result {
+        Ok(person) => {
+            let response = ResponseMessageDto::Person {
+                person: Some(person.clone()),
+            };
+            let change = PersonChanged {
+                kind,
+                person,
+                previous,
+            };
+            (response, Some(change))
+        }
+        Err(e) => (rejection(e), None),
+    }
+}
+
+// Database failures are logged here and only summarised to the caller.
+fn rejection(e: StoreError) -> ResponseMessageDto {
+    let error = match e {
+        StoreError::Rejected(error) => error,
+        StoreError::Database(e) => {
+            warn!("Database error: {e}");
+            CommandError::Storage {
+                message: "the person store is unavailable".to_string(),
+            }
+        }
+    };
+    ResponseMessageDto::Rejected { error }
 }
@@ -1,3 +1,2 @@
 pub mod kafka_consumer;
-pub mod kafka_producer;
-pub mod models;
+pub mod store;
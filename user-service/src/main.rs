@@ -1,10 +1,13 @@
 use std::env;
+use std::sync::Arc;
 
-use user_service::{kafka_consumer::IngestConsumer, kafka_producer::create_kafka_producer};
+use transport::{kafka::KafkaTransport, StartFrom, Transport};
+use user_service::{kafka_consumer::IngestConsumer, store::PersonStore};
 
 const DEFAULT_BROKERS: &str = "localhost:29092";
 const DEFAULT_CONSUMER_GROUP_ID: &str = "1";
 const DEFAULT_LISTEN_TOPIC: &str = "from_router";
+const DEFAULT_DATABASE_PATH: &str = "persons.db";
 
 #[tokio::main]
 async fn main() {
@@ -29,9 +32,18 @@ async fn main() {
                 .collect::<Vec<String>>()
         })
         .unwrap_or(vec![DEFAULT_LISTEN_TOPIC.to_string()]);
+    let database_path =
+        env::var("USER_SERVICE_DATABASE_PATH").unwrap_or(DEFAULT_DATABASE_PATH.to_string());
 
-    let producer = create_kafka_producer(brokers.as_str()).unwrap();
-    let ingest_consumer = IngestConsumer::new(brokers, group_id, listen_topics, producer)
-        .expect("Failed to create ingest consumer");
+    let store = PersonStore::open(&database_path)
+        .unwrap_or_else(|e| panic!("Failed to open the person store at {database_path}: {e}"));
+
+    let transport: Arc<dyn Transport> = Arc::new(KafkaTransport::new(&brokers).unwrap());
+    let listen_topics: Vec<&str> = listen_topics.iter().map(String::as_str).collect();
+    let subscription = transport
+        .subscribe(&group_id, &listen_topics, StartFrom::Latest)
+        .await
+        .expect("Failed to subscribe to request topics");
+    let mut ingest_consumer = IngestConsumer::new(subscription, transport, store);
     ingest_consumer.run().await;
 }
@@ -0,0 +1,277 @@
+use std::fmt;
+use std::path::Path;
+
+use protocol::messages::{CommandError, PageRequest, PersonPage, MAX_PAGE_SIZE};
+use protocol::models::{Id, Person};
+use protocol::validation::trimmed;
+use rusqlite::{params, params_from_iter, Connection, ErrorCode, OptionalExtension, Row};
+
+const MAX_FIELD_LENGTH: usize = 100;
+
+// The filters of `find_page`, on parameters 1 and 2, and of the queries it makes for the persons
+// either side of the page. `instr` rather than `LIKE`, so
+// `%` and `_` in the filter match themselves.
+const FILTER: &str = "(?1 IS NULL OR lower(department) = lower(?1))
+    AND (?2 IS NULL OR instr(lower(name), lower(?2)) > 0)";
+
+/// Schema changes, applied in order. `PRAGMA user_version` records how many have been applied,
+/// so each runs exactly once per database; append new ones, never edit old ones.
+const MIGRATIONS: &[&str] = &[
+    // `AUTOINCREMENT` keeps numbers from being reused, even after the highest one is deleted.
+    "CREATE TABLE persons (
+        number INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        department TEXT NOT NULL
+    )",
+    "INSERT INTO persons (number, name, department) VALUES
+        (1, 'Alice', 'Executive'),
+        (2, 'Bob', 'Finance'),
+        (3, 'Charlie', 'Operations')",
+    // Numbers travel as `i32`, so the table refuses any beyond. SQLite cannot add a CHECK to a
+    // table, so it is rebuilt, keeping its AUTOINCREMENT sequence so no number is reused.
+    "CREATE TABLE persons_checked (
+        number INTEGER PRIMARY KEY AUTOINCREMENT CHECK (number BETWEEN 1 AND 2147483647),
+        name TEXT NOT NULL,
+        department TEXT NOT NULL
+    );
+    INSERT INTO persons_checked (number, name, department)
+        SELECT number, name, department FROM persons;
+    DELETE FROM sqlite_sequence WHERE name = 'persons_checked';
+    INSERT INTO sqlite_sequence (name, seq)
+        SELECT 'persons_checked', seq FROM sqlite_sequence WHERE name = 'persons';
+    DROP TABLE persons;
+    ALTER TABLE persons_checked RENAME TO persons;",
+];
+
+/// Why the store could not carry out an operation.
+#[derive(Debug)]
+pub enum StoreError {
+    /// The operation was refused; the database is unchanged.
+    Rejected(CommandError),
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Rejected(error) => write!(f, "{error}"),
+            StoreError::Database(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<CommandError> for StoreError {
+    fn from(error: CommandError) -> Self {
+        StoreError::Rejected(error)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Database(e)
+    }
+}
+
+/// The persons the user-service knows about, kept in an SQLite database.
+pub struct PersonStore {
+    connection: Connection,
+}
+
+impl PersonStore {
+    /// Opens the database at `path`, creating and seeding it on first start.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        Self::migrate(Connection::open(path)?)
+    }
+
+    /// A seeded database that lives only as long as the store.
+    pub fn in_memory() -> Result<Self, StoreError> {
+        Self::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(mut connection: Connection) -> Result<Self, StoreError> {
+        let transaction = connection.transaction()?;
+        let applied: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for migration in MIGRATIONS.iter().skip(applied) {
+            transaction.execute_batch(migration)?;
+        }
+        transaction.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        transaction.commit()?;
+        Ok(Self { connection })
+    }
+
+    pub fn get(&self, number: i32) -> Result<Option<Person>, StoreError> {
+        let person = self
+            .connection
+            .query_row(
+                "SELECT number, name, department FROM persons WHERE number = ?1",
+                [number],
+                person_from_row,
+            )
+            .optional()?;
+        Ok(person)
+    }
+
+    /// The persons with any of the numbers, in no particular order; numbers no person has are
+    /// left out, and a number given twice is one person.
+    pub fn get_many(&self, numbers: &[i32]) -> Result<Vec<Person>, StoreError> {
+        let placeholders = vec!["?"; numbers.len()].join(", ");
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT number, name, department FROM persons WHERE number IN ({placeholders})"
+        ))?;
+        let persons = statement
+            .query_map(params_from_iter(numbers), person_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(persons)
+    }
+
+    /// A page of the persons matching every filter that is set, in order of number, and whether
+    /// any that match come before or after it. Both filters ignore case.
+    pub fn find_page(
+        &self,
+        department: Option<&str>,
+        name_contains: Option<&str>,
+        page: &PageRequest,
+    ) -> Result<PersonPage, StoreError> {
+        if page.limit > MAX_PAGE_SIZE {
+            return Err(CommandError::Validation {
+                field: "limit".to_string(),
+                message: format!("must be at most {MAX_PAGE_SIZE}"),
+            }
+            .into());
+        }
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT number, name, department FROM persons
+             WHERE {FILTER} AND (?3 IS NULL OR number > ?3) AND (?4 IS NULL OR number < ?4)
+             ORDER BY number {} LIMIT ?5",
+            if page.from_end { "DESC" } else { "ASC" }
+        ))?;
+        let mut persons: Vec<Person> = statement
+            .query_map(
+                params![
+                    department,
+                    name_contains,
+                    page.after,
+                    page.before,
+                    page.limit
+                ],
+                person_from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+        if page.from_end {
+            persons.reverse();
+        }
+        // The numbers the page runs from and to. An empty page sits next to the bound it was
+        // read from, so it runs from just past that bound to just before it.
+        let (from, to) = match (persons.first(), persons.last()) {
+            (Some(first), Some(last)) => (i64::from(first.id.number), i64::from(last.id.number)),
+            _ if page.from_end => {
+                let before = page.before.map_or(i64::MAX, i64::from);
+                (before, before - 1)
+            }
+            _ => {
+                let after = page.after.map_or(i64::MIN, i64::from);
+                (after + 1, after)
+            }
+        };
+        let any = |condition: &str, number: i64| {
+            self.connection.query_row(
+                &format!("SELECT EXISTS (SELECT 1 FROM persons WHERE {FILTER} AND {condition})"),
+                params![department, name_contains, number],
+                |row| row.get(0),
+            )
+        };
+        Ok(PersonPage {
+            has_previous: any("number < ?3", from)?,
+            has_next: any("number > ?3", to)?,
+            persons,
+        })
+    }
+
+    /// Adds a person under the next free number.
+    ///
+    /// Fails with `NumbersExhausted` once every number up to `i32::MAX` has been used.
+    pub fn create(&mut self, name: &str, department: &str) -> Result<Person, StoreError> {
+        let name = validate("name", name)?;
+        let department = validate("department", department)?;
+        let inserted = self.connection.execute(
+            "INSERT INTO persons (name, department) VALUES (?1, ?2)",
+            params![name, department],
+        );
+        match inserted {
+            // The only constraint a new person can break is the CHECK on its number.
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                return Err(CommandError::NumbersExhausted.into());
+            }
+            inserted => inserted?,
+        };
+        let number = i32::try_from(self.connection.last_insert_rowid())
+            .map_err(|_| CommandError::NumbersExhausted)?;
+        Ok(Person {
+            name,
+            id: Id { number, department },
+        })
+    }
+
+    /// Changes the fields that are given and leaves the others as they are.
+    pub fn update(
+        &mut self,
+        number: i32,
+        name: Option<&str>,
+        department: Option<&str>,
+    ) -> Result<Person, StoreError> {
+        // Validates everything before changing anything, so a rejected update leaves no trace.
+        let name = name.map(|name| validate("name", name)).transpose()?;
+        let department = department
+            .map(|department| validate("department", department))
+            .transpose()?;
+        let updated = self.connection.execute(
+            "UPDATE persons SET name = coalesce(?2, name), department = coalesce(?3, department)
+             WHERE number = ?1",
+            params![number, name, department],
+        )?;
+        if updated == 0 {
+            return Err(CommandError::NotFound { number }.into());
+        }
+        self.get(number)?
+            .ok_or_else(|| CommandError::NotFound { number }.into())
+    }
+
+    /// Removes the person and returns it as it was.
+    pub fn delete(&mut self, number: i32) -> Result<Person, StoreError> {
+        let transaction = self.connection.transaction()?;
+        let person = transaction
+            .query_row(
+                "SELECT number, name, department FROM persons WHERE number = ?1",
+                [number],
+                person_from_row,
+            )
+            .optional()?
+            .ok_or(CommandError::NotFound { number })?;
+        transaction.execute("DELETE FROM persons WHERE number = ?1", [number])?;
+        transaction.commit()?;
+        Ok(person)
+    }
+}
+
+fn person_from_row(row: &Row) -> rusqlite::Result<Person> {
+    Ok(Person {
+        name: row.get("name")?,
+        id: Id {
+            number: row.get("number")?,
+            department: row.get("department")?,
+        },
+    })
+}
+
+fn validate(field: &str, value: &str) -> Result<String, CommandError> {
+    trimmed(value, MAX_FIELD_LENGTH)
+        .map(str::to_string)
+        .map_err(|message| CommandError::Validation {
+            field: field.to_string(),
+            message,
+        })
+}
@@ -0,0 +1,241 @@
+use protocol::messages::{CommandError, PageRequest, PersonPage, MAX_PAGE_SIZE};
+use protocol::models::Person;
+use rusqlite::Connection;
+use tempfile::TempDir;
+use user_service::store::{PersonStore, StoreError};
+
+// Every person matching the filters, which in these tests fit on one page.
+fn find(store: &PersonStore, department: Option<&str>, name_contains: Option<&str>) -> Vec<Person> {
+    let request = PageRequest {
+        after: None,
+        before: None,
+        limit: MAX_PAGE_SIZE,
+        from_end: false,
+    };
+    store
+        .find_page(department, name_contains, &request)
+        .unwrap()
+        .persons
+}
+
+fn names(store: &PersonStore) -> Vec<String> {
+    find(store, None, None)
+        .into_iter()
+        .map(|person| person.name)
+        .collect()
+}
+
+#[test]
+fn new_database_is_seeded() {
+    let dir = TempDir::new().unwrap();
+
+    let store = PersonStore::open(dir.path().join("persons.db")).unwrap();
+
+    assert_eq!(names(&store), ["Alice", "Bob", "Charlie"]);
+}
+
+#[test]
+fn changes_survive_a_restart() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("persons.db");
+    {
+        let mut store = PersonStore::open(&path).unwrap();
+        store.create("Dana", "Finance").unwrap();
+        store.update(2, Some("Robert"), None).unwrap();
+        store.delete(3).unwrap();
+    }
+
+    let store = PersonStore::open(&path).unwrap();
+
+    assert_eq!(names(&store), ["Alice", "Robert", "Dana"]);
+    assert_eq!(store.get(4).unwrap().unwrap().id.department, "Finance");
+}
+
+#[test]
+fn deleted_numbers_are_not_reused_after_a_restart() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("persons.db");
+    {
+        let mut store = PersonStore::open(&path).unwrap();
+        store.create("Dana", "Finance").unwrap();
+        store.delete(4).unwrap();
+    }
+
+    let mut store = PersonStore::open(&path).unwrap();
+    let person = store.create("Eve", "Finance").unwrap();
+
+    assert_eq!(person.id.number, 5);
+}
+
+#[test]
+fn creates_fail_once_every_number_is_used() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("persons.db");
+    drop(PersonStore::open(&path).unwrap());
+    Connection::open(&path)
+        .unwrap()
+        .execute(
+            "INSERT INTO persons (number, name, department) VALUES (?1, 'Zed', 'Finance')",
+            [i32::MAX],
+        )
+        .unwrap();
+
+    let mut store = PersonStore::open(&path).unwrap();
+    let error = store.create("Dana", "Finance").unwrap_err();
+
+    assert!(matches!(
+        error,
+        StoreError::Rejected(CommandError::NumbersExhausted)
+    ));
+    assert_eq!(names(&store), ["Alice", "Bob", "Charlie", "Zed"]);
+}
+
+#[test]
+fn persons_are_got_by_number_leaving_out_unknown_numbers() {
+    let store = PersonStore::in_memory().unwrap();
+
+    let persons = store.get_many(&[3, 7, 1, 3]).unwrap();
+
+    let mut names: Vec<_> = persons.iter().map(|person| person.name.as_str()).collect();
+    names.sort_unstable();
+    assert_eq!(names, ["Alice", "Charlie"]);
+}
+
+#[test]
+fn filters_ignore_case_and_match_wildcards_literally() {
+    let mut store = PersonStore::in_memory().unwrap();
+    store.create("100% Bob", "Finance").unwrap();
+
+    let finance = find(&store, Some("FINANCE"), Some("bob"));
+    let percent = find(&store, None, Some("%"));
+
+    assert_eq!(finance.len(), 2);
+    assert_eq!(percent.len(), 1);
+    assert_eq!(percent[0].name, "100% Bob");
+}
+
+// Seeds persons 4 to 10 alongside the first three, and deletes 6.
+fn ten_persons() -> PersonStore {
+    let mut store = PersonStore::in_memory().unwrap();
+    for name in ["Dana", "Eve", "Frank", "Grace", "Heidi", "Ivan", "Judy"] {
+        store.create(name, "Finance").unwrap();
+    }
+    store.delete(6).unwrap();
+    store
+}
+
+fn page(
+    store: &PersonStore,
+    after: Option<i32>,
+    before: Option<i32>,
+    limit: u32,
+    from_end: bool,
+) -> (Vec<i32>, bool, bool) {
+    let request = PageRequest {
+        after,
+        before,
+        limit,
+        from_end,
+    };
+    let PersonPage {
+        persons,
+        has_previous,
+        has_next,
+    } = store.find_page(None, None, &request).unwrap();
+    let numbers = persons.into_iter().map(|person| person.id.number).collect();
+    (numbers, has_previous, has_next)
+}
+
+#[test]
+fn pages_run_forward_from_after() {
+    let store = ten_persons();
+
+    assert_eq!(
+        page(&store, None, None, 3, false),
+        (vec![1, 2, 3], false, true)
+    );
+    assert_eq!(
+        page(&store, Some(3), None, 3, false),
+        (vec![4, 5, 7], true, true)
+    );
+    assert_eq!(
+        page(&store, Some(8), None, 3, false),
+        (vec![9, 10], true, false)
+    );
+    assert_eq!(
+        page(&store, Some(10), None, 3, false),
+        (vec![], true, false)
+    );
+}
+
+#[test]
+fn pages_run_backward_from_before() {
+    let store = ten_persons();
+
+    assert_eq!(
+        page(&store, None, None, 3, true),
+        (vec![8, 9, 10], true, false)
+    );
+    assert_eq!(
+        page(&store, None, Some(8), 3, true),
+        (vec![4, 5, 7], true, true)
+    );
+    assert_eq!(
+        page(&store, None, Some(3), 3, true),
+        (vec![1, 2], false, true)
+    );
+    assert_eq!(
+        page(&store, Some(2), Some(5), 3, true),
+        (vec![3, 4], true, true)
+    );
+}
+
+#[test]
+fn empty_pages_tell_whether_persons_are_either_side() {
+    let store = ten_persons();
+
+    assert_eq!(page(&store, None, None, 0, false), (vec![], false, true));
+    assert_eq!(page(&store, None, None, 0, true), (vec![], true, false));
+    assert_eq!(
+        page(&store, Some(5), Some(7), 3, false),
+        (vec![], true, true)
+    );
+}
+
+#[test]
+fn page_filters_and_limit() {
+    let store = ten_persons();
+    let request = PageRequest {
+        after: Some(4),
+        before: None,
+        limit: 1,
+        from_end: false,
+    };
+
+    let page = store.find_page(Some("finance"), None, &request).unwrap();
+    let numbers: Vec<_> = page.persons.iter().map(|person| person.id.number).collect();
+    assert_eq!(numbers, [5]);
+    assert!(page.has_previous && page.has_next);
+
+    let too_many = PageRequest {
+        limit: 101,
+        ..request
+    };
+    assert!(matches!(
+        store.find_page(None, None, &too_many),
+        Err(StoreError::Rejected(CommandError::Validation { field, .. })) if field == "limit"
+    ));
+}
+
+#[test]
+fn rejected_update_leaves_the_person_unchanged() {
+    let mut store = PersonStore::in_memory().unwrap();
+
+    let result = store.update(2, Some("Robert"), Some(" "));
+
+    assert!(matches!(
+        result,
+        Err(StoreError::Rejected(CommandError::Validation { field, .. })) if field == "department"
+    ));
+    assert_eq!(store.get(2).unwrap().unwrap().name, "Bob");
+}